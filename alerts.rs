@@ -0,0 +1,124 @@
+use std::fmt;
+
+use crate::uses::*;
+
+/// When a topic counts as stalled, e.g. `0 for 2m`, or `orders.*: 10% of 1h`
+#[derive(Debug, Clone)]
+pub struct StallRule {
+    /// Topics the rule applies to, all if none
+    pub topics: Option<Regex>,
+    pub condition: Condition,
+    spec: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    /// Average rate over `window` at most `max_rate`
+    Slow { max_rate: f64, window: Duration },
+    /// Latest rate below `fraction` of the average rate over `window`
+    Drop { fraction: f64, window: Duration },
+}
+
+impl StallRule {
+    pub fn applies_to(&self, topic: &str) -> bool {
+        self.topics.as_ref().map_or(true, |re| re.is_match(topic))
+    }
+}
+
+impl std::str::FromStr for StallRule {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (topics, condition) = match spec.rsplit_once(": ") {
+            Some((topics, condition)) => (Some(Regex::new(topics.trim())?), condition),
+            None => (None, spec),
+        };
+        let duration = |d: &str| -> Result<Duration> {
+            Ok(d.trim()
+                .parse::<humantime::Duration>()
+                .context(format!("Not a parseable time: {}", d))?
+                .into())
+        };
+        let condition = if let Some((percent, window)) = condition.split_once("% of ") {
+            Condition::Drop {
+                fraction: percent.trim().parse::<f64>()? / 100.,
+                window: duration(window)?,
+            }
+        } else if let Some((max_rate, window)) = condition.split_once(" for ") {
+            Condition::Slow {
+                max_rate: max_rate.trim().parse()?,
+                window: duration(window)?,
+            }
+        } else {
+            anyhow::bail!(
+                "Expected a stall rule like \"0 for 2m\" or \"10% of 1h\", got {}",
+                spec
+            );
+        };
+        Ok(StallRule {
+            topics,
+            condition,
+            spec: spec.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for StallRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.spec)
+    }
+}
+
+/// A rule that currently holds for a topic
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub topic: TopicName,
+    pub rule: Arc<StallRule>,
+    /// Latest rate when the rule was evaluated
+    pub rate: Option<f64>,
+    pub since: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub enum AlertEvent {
+    Firing(Alert),
+    Resolved(Alert),
+}
+
+impl AlertEvent {
+    pub fn alert(&self) -> &Alert {
+        match self {
+            AlertEvent::Firing(alert) | AlertEvent::Resolved(alert) => alert,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rules() {
+        let rule = "0 for 2m".parse::<StallRule>().unwrap();
+        assert!(rule.applies_to("anything"));
+        assert_eq!(
+            rule.condition,
+            Condition::Slow {
+                max_rate: 0.,
+                window: Duration::from_secs(120)
+            }
+        );
+        let rule = "orders|payments: 10% of 1h".parse::<StallRule>().unwrap();
+        assert!(rule.applies_to("orders") && !rule.applies_to("clicks"));
+        assert_eq!(
+            rule.condition,
+            Condition::Drop {
+                fraction: 0.1,
+                window: Duration::from_secs(3600)
+            }
+        );
+        assert_eq!(rule.to_string(), "orders|payments: 10% of 1h");
+        assert!("fast please".parse::<StallRule>().is_err());
+        assert!("0 for ever".parse::<StallRule>().is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+
+use crate::{ui::format_number, uses::*};
+
+/// Exit codes as Nagios and friends understand them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok = 0,
+    Warn = 1,
+    Crit = 2,
+}
+
+/// Topics that must be producing at some rate, e.g. `orders`, or `payments-*:5:10`
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    spec: String,
+    pattern: Regex,
+    /// CRIT below this, falls back to --min-rate
+    min_rate: Option<f64>,
+    /// WARN below this, falls back to --warn-rate
+    warn_rate: Option<f64>,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Status::Ok => "OK",
+            Status::Warn => "WARN",
+            Status::Crit => "CRIT",
+        })
+    }
+}
+
+impl std::str::FromStr for Assertion {
+    type Err = anyhow::Error;
+
+    /// A glob, optionally followed by `:MIN_RATE` and `:WARN_RATE`. Kafka doesn't allow colons in topic names.
+    fn from_str(spec: &str) -> Result<Self> {
+        let mut parts = spec.split(':');
+        let glob = parts.next().unwrap_or_default();
+        anyhow::ensure!(!glob.is_empty(), "Empty topic pattern in {}", spec);
+        let mut rate = || -> Result<Option<f64>> {
+            match parts.next() {
+                None | Some("") => Ok(None),
+                Some(rate) => Ok(Some(rate.parse().context(format!("Not a rate: {}", rate))?)),
+            }
+        };
+        let (min_rate, warn_rate) = (rate()?, rate()?);
+        anyhow::ensure!(
+            parts.next().is_none(),
+            "Expected PATTERN[:MIN_RATE[:WARN_RATE]], got {}",
+            spec
+        );
+        let pattern = glob
+            .split('*')
+            .map(|part| part.split('?').map(regex::escape).join("."))
+            .join(".*");
+        Ok(Assertion {
+            spec: glob.to_owned(),
+            pattern: Regex::new(&format!("^{}$", pattern))?,
+            min_rate,
+            warn_rate,
+        })
+    }
+}
+
+impl Assertion {
+    /// Fill in thresholds not given in the pattern itself
+    fn or(mut self, min_rate: Option<f64>, warn_rate: Option<f64>) -> Result<Self> {
+        self.min_rate = self.min_rate.or(min_rate);
+        self.warn_rate = self.warn_rate.or(warn_rate);
+        anyhow::ensure!(
+            self.min_rate.is_some() || self.warn_rate.is_some(),
+            "No rate to check {} against, use --min-rate or {}:RATE",
+            self.spec,
+            self.spec
+        );
+        Ok(self)
+    }
+
+    fn status(&self, rate: f64) -> Status {
+        if self.min_rate.map_or(false, |min| rate < min) {
+            Status::Crit
+        } else if self.warn_rate.map_or(false, |warn| rate < warn) {
+            Status::Warn
+        } else {
+            Status::Ok
+        }
+    }
+}
+
+/// Scrape until all assertions hold, or until `within` has passed, and return the outcome
+pub(crate) fn run(
+    opts: &Opts,
+    assertions: &[Assertion],
+    min_rate: Option<f64>,
+    warn_rate: Option<f64>,
+    within: Duration,
+    mut stats: Stats,
+) -> Result<Status> {
+    anyhow::ensure!(
+        opts.rounds >= 2,
+        "Need at least two rounds to compute rates"
+    );
+    let assertions = assertions
+        .iter()
+        .map(|assertion| assertion.clone().or(min_rate, warn_rate))
+        .collect::<Result<Vec<_>>>()?;
+    let deadline = stats.clock().now() + within;
+    loop {
+        stats.ingest()?;
+        let timed_out = stats.clock().now() >= deadline;
+        if stats.rounds >= opts.rounds || timed_out {
+            let rates = stats
+                .basestats()
+                .filter(|stats| stats.topic.stat_idx == 0)
+                .map(|stats| (stats.topic.name, stats.rate))
+                .collect::<Vec<_>>();
+            let (status, summary) = assess(&assertions, &rates);
+            if status == Status::Ok || timed_out {
+                if let Some(err) = stats.metadata_error.as_ref() {
+                    eprintln!("{}", err);
+                }
+                println!("{} - {}", status, summary);
+                return Ok(status);
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Worst status over all matching topics, and a one-line summary listing the worst first
+fn assess(assertions: &[Assertion], rates: &[(TopicName, Option<f64>)]) -> (Status, String) {
+    let mut results = Vec::new();
+    for assertion in assertions {
+        let mut matched = false;
+        for (topic, rate) in rates {
+            if !assertion.pattern.is_match(topic) {
+                continue;
+            }
+            matched = true;
+            results.push(match rate {
+                Some(rate) => {
+                    let status = assertion.status(*rate);
+                    let threshold = match status {
+                        Status::Ok => None,
+                        Status::Warn => assertion.warn_rate,
+                        Status::Crit => assertion.min_rate,
+                    };
+                    let threshold = threshold
+                        .map(|t| format!(" < {}", format_number(t)))
+                        .unwrap_or_default();
+                    let text = format!("{} {}/s{}", topic, format_number(*rate), threshold);
+                    (status, text)
+                }
+                None => (Status::Crit, format!("{} has no rate yet", topic)),
+            });
+        }
+        if !matched {
+            results.push((Status::Crit, format!("no topic matches {}", assertion.spec)));
+        }
+    }
+    results.sort_by_key(|(status, _)| cmp::Reverse(*status));
+    let status = results.first().map_or(Status::Ok, |(status, _)| *status);
+    (status, results.into_iter().map(|(_, text)| text).join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assesses_globs() {
+        let assertions = ["orders", "payments-*:5:10"]
+            .iter()
+            .map(|spec| {
+                spec.parse::<Assertion>()
+                    .unwrap()
+                    .or(Some(1.), None)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let rates = |rates: &[(&str, Option<f64>)]| {
+            rates
+                .iter()
+                .map(|&(topic, rate)| (TopicName::new(topic), rate))
+                .collect::<Vec<_>>()
+        };
+        let (status, _) = assess(
+            &assertions,
+            &rates(&[
+                ("orders", Some(3.)),
+                ("payments-eu", Some(12.)),
+                ("x", None),
+            ]),
+        );
+        assert_eq!(status, Status::Ok);
+        let (status, summary) = assess(
+            &assertions,
+            &rates(&[("orders", Some(3.)), ("payments-eu", Some(7.))]),
+        );
+        assert_eq!(status, Status::Warn);
+        assert!(summary.starts_with("payments-eu"), "{}", summary);
+        let (status, summary) = assess(&assertions, &rates(&[("orders", Some(0.))]));
+        assert_eq!(status, Status::Crit);
+        assert!(
+            summary.contains("no topic matches payments-*"),
+            "{}",
+            summary
+        );
+        assert!(":5".parse::<Assertion>().is_err());
+        assert!("orders:5:10:20".parse::<Assertion>().is_err());
+        assert!("orders"
+            .parse::<Assertion>()
+            .unwrap()
+            .or(None, None)
+            .is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::uses::*;
+
+/// Source of "now" for everything downstream of the scrapers.
+/// Usually the real time, but a replay can run ahead, speed up or be rewound,
+/// and a simulation only moves when told to.
+#[derive(Clone)]
+pub enum Clock {
+    Real,
+    Replay(Arc<ReplayClock>),
+    Sim(Arc<SimClock>),
+}
+
+impl Clock {
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::Real => Instant::now(),
+            Clock::Replay(replay) => replay.now(),
+            Clock::Sim(sim) => sim.now(),
+        }
+    }
+
+    /// Wall clock time at a given instant of this clock
+    pub fn wall_at(&self, instant: Instant) -> DateTime<Local> {
+        let (now, wall) = match self {
+            Clock::Real => (Instant::now(), Local::now()),
+            Clock::Replay(replay) => (replay.now(), replay.wall_now()),
+            Clock::Sim(sim) => (sim.now(), sim.wall_now()),
+        };
+        let shift = |d| chrono::Duration::from_std(d).unwrap_or_else(|_| chrono::Duration::zero());
+        match instant.checked_duration_since(now) {
+            Some(ahead) => wall + shift(ahead),
+            None => wall - shift(now - instant),
+        }
+    }
+
+    pub fn wall_now(&self) -> DateTime<Local> {
+        match self {
+            Clock::Real => Local::now(),
+            Clock::Replay(replay) => replay.wall_now(),
+            Clock::Sim(sim) => sim.wall_now(),
+        }
+    }
+}
+
+pub struct ReplayClock {
+    inner: Mutex<ReplayState>,
+}
+
+struct ReplayState {
+    /// Real time at which the replay was at `position`
+    anchor: Instant,
+    position: Instant,
+    speed: f64,
+    /// Earliest position, and its wall clock time in the recording
+    start: Instant,
+    start_wall: DateTime<Local>,
+    /// Increased on every backwards seek, so the replayer knows to start over
+    rewinds: usize,
+}
+
+impl ReplayClock {
+    pub fn new(start_wall: DateTime<Local>, speed: f64) -> Self {
+        let now = Instant::now();
+        ReplayClock {
+            inner: Mutex::new(ReplayState {
+                anchor: now,
+                position: now,
+                speed,
+                start: now,
+                start_wall,
+                rewinds: 0,
+            }),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.inner.lock().expect("poisoned").now()
+    }
+
+    pub fn wall_now(&self) -> DateTime<Local> {
+        let state = self.inner.lock().expect("poisoned");
+        state.start_wall
+            + chrono::Duration::from_std(state.now() - state.start)
+                .unwrap_or_else(|_| chrono::Duration::zero())
+    }
+
+    /// The instant that corresponds to a wall clock time in the recording
+    pub fn instant_at(&self, wall: DateTime<Local>) -> Instant {
+        let state = self.inner.lock().expect("poisoned");
+        state.start + (wall - state.start_wall).to_std().unwrap_or_default()
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.inner.lock().expect("poisoned").speed
+    }
+
+    pub fn set_speed(&self, speed: f64) {
+        let mut state = self.inner.lock().expect("poisoned");
+        state.position = state.now();
+        state.anchor = Instant::now();
+        state.speed = speed;
+    }
+
+    /// Jump forward or (if `back`) backward
+    pub fn seek(&self, by: Duration, back: bool) {
+        let mut state = self.inner.lock().expect("poisoned");
+        let now = state.now();
+        state.anchor = Instant::now();
+        state.position = match back {
+            false => now + by,
+            true => {
+                state.rewinds += 1;
+                cmp::max(now.checked_sub(by).unwrap_or(state.start), state.start)
+            }
+        };
+    }
+
+    pub fn rewinds(&self) -> usize {
+        self.inner.lock().expect("poisoned").rewinds
+    }
+}
+
+impl ReplayState {
+    fn now(&self) -> Instant {
+        self.position + self.anchor.elapsed().mul_f64(self.speed)
+    }
+}
+
+/// A clock that stands still until it's [advanced](Self::advance)
+pub struct SimClock {
+    inner: Mutex<(Instant, DateTime<Local>)>,
+}
+
+impl SimClock {
+    pub fn new(wall: DateTime<Local>) -> Self {
+        SimClock {
+            inner: Mutex::new((Instant::now(), wall)),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.inner.lock().expect("poisoned").0
+    }
+
+    pub fn wall_now(&self) -> DateTime<Local> {
+        self.inner.lock().expect("poisoned").1
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut inner = self.inner.lock().expect("poisoned");
+        inner.0 += by;
+        inner.1 += chrono::Duration::from_std(by).unwrap_or_else(|_| chrono::Duration::zero());
+    }
+}
@@ -4,37 +4,59 @@ use crate::uses::*;
 
 pub struct ColorAssignment {
     inner: HashMap<Topic, LineColor>,
+    /// Topics that are plotted regardless of their rank
+    pinned: HashSet<Topic>,
 }
 
 impl ColorAssignment {
     pub fn new() -> Self {
         Self {
             inner: HashMap::new(),
+            pinned: HashSet::new(),
         }
     }
 
     pub fn compute(&mut self, basestats: &[stats::TopicStats]) {
         let mut colors = LineColor::all_variants();
-        let mut topdogs = basestats
-            .iter()
-            .take(colors.len())
-            .filter(|s| s.seen > 0)
-            .filter(|s| s.topic.stat_idx == 0)
-            .map(|s| s.topic.clone())
-            .collect::<HashSet<_>>();
+        let present = basestats.iter().map(|s| &s.topic).collect::<HashSet<_>>();
+        self.pinned.retain(|topic| present.contains(topic));
+        let mut topdogs = self.pinned.clone();
+        topdogs.extend(
+            basestats
+                .iter()
+                .filter(|s| !self.pinned.contains(&s.topic))
+                .take(colors.len() - self.pinned.len())
+                .filter(|s| s.seen > 0)
+                .filter(|s| s.topic.stat_idx == 0)
+                .map(|s| s.topic.clone()),
+        );
         self.inner.retain(|topic, _| topdogs.contains(topic));
         for color in self.inner.values() {
             colors.remove(color);
         }
         let mut colors = colors.drain();
         for dog in topdogs.drain() {
-            // TODO: It's high time for interned topic string names
             self.inner
                 .entry(dog)
                 .or_insert_with(|| colors.next().expect("no more dogs than colors"));
         }
     }
 
+    /// Toggle whether a topic is always plotted. Returns false if there is no color left to pin it with.
+    pub fn toggle_pin(&mut self, topic: &Topic) -> bool {
+        if self.pinned.remove(topic) {
+            true
+        } else if self.pinned.len() < LineColor::all_variants().len() {
+            self.pinned.insert(topic.clone())
+        } else {
+            false
+        }
+    }
+
+    pub fn is_pinned(&self, topic: &Topic) -> bool {
+        self.pinned.contains(topic)
+    }
+
     pub fn get(&self, topic: &Topic) -> Color {
         self.inner
             .get(topic)
@@ -42,13 +64,24 @@ impl ColorAssignment {
             .unwrap_or(Color::Gray)
     }
 
-    pub fn colored_topic_names(&self) -> impl Iterator<Item = &str> {
-        self.inner.keys().map(|Topic { name, .. }| name.as_ref())
+    /// Whether any generation of a topic with this name got a color
+    pub fn colors_name(&self, name: &TopicName) -> bool {
+        self.inner.keys().any(|topic| topic.name == *name)
     }
 
     pub fn len(&self) -> usize {
         self.inner.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+impl Default for ColorAssignment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // Bit stupid to duplicate this here, but Color can't be inserted into a hash map
@@ -63,9 +96,9 @@ enum LineColor {
     White,
 }
 
-impl Into<Color> for LineColor {
-    fn into(self) -> Color {
-        match self {
+impl From<LineColor> for Color {
+    fn from(color: LineColor) -> Color {
+        match color {
             LineColor::Blue => Color::Blue,
             LineColor::Yellow => Color::Yellow,
             LineColor::Red => Color::Red,
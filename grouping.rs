@@ -0,0 +1,68 @@
+use crate::uses::*;
+
+/// How topics are combined into aggregates.
+/// Aggregate names end in `*`, which Kafka doesn't allow in topic names, so they can't clash with real topics.
+#[derive(Default, Debug, Clone)]
+pub struct Grouping {
+    /// Topics matching one of these are grouped by the capture named `group`, the first one, or the whole match
+    regexes: Vec<Regex>,
+    /// Otherwise, topics are grouped by this many leading `.`-separated segments
+    depth: Option<usize>,
+}
+
+impl Grouping {
+    pub fn new(regexes: Vec<Regex>, depth: Option<usize>) -> Self {
+        Grouping { regexes, depth }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regexes.is_empty() && self.depth.is_none()
+    }
+
+    /// Name of the aggregate a topic belongs to, if any
+    pub fn aggregate_of(&self, topic: &str) -> Option<TopicName> {
+        for re in &self.regexes {
+            if let Some(captures) = re.captures(topic) {
+                let key = captures
+                    .name("group")
+                    .or_else(|| captures.get(1))
+                    .or_else(|| captures.get(0))?;
+                return Some(TopicName::new(&format!("{}*", key.as_str())));
+            }
+        }
+        let depth = self.depth.filter(|&depth| depth > 0)?;
+        let (prefix, _) = topic.match_indices('.').nth(depth - 1)?;
+        Some(TopicName::new(&format!("{}.*", &topic[..prefix])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regexes_before_depth() {
+        let grouping = Grouping::new(
+            vec![
+                Regex::new(r"^(?P<team>\w+)\.(?P<group>\w+)\.audit\.").unwrap(),
+                Regex::new(r"^legacy-").unwrap(),
+            ],
+            Some(2),
+        );
+        let aggregate = |topic| grouping.aggregate_of(topic).map(|name| name.to_string());
+        assert_eq!(
+            aggregate("shop.orders.created.v1").as_deref(),
+            Some("shop.orders.*")
+        );
+        assert_eq!(
+            aggregate("shop.orders.audit.v2").as_deref(),
+            Some("orders*")
+        );
+        assert_eq!(aggregate("legacy-payments").as_deref(), Some("legacy-*"));
+        assert_eq!(aggregate("shop.orders"), None);
+        assert_eq!(aggregate("standalone"), None);
+        assert!(Grouping::default()
+            .aggregate_of("shop.orders.created")
+            .is_none());
+    }
+}
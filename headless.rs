@@ -0,0 +1,174 @@
+use crate::ui::{format_number, right_align};
+use crate::uses::*;
+
+pub(crate) fn table(opts: &Opts, mut stats: Stats) -> Result<()> {
+    anyhow::ensure!(
+        opts.rounds >= 2,
+        "Need at least two rounds to compute rates"
+    );
+    while stats.rounds < opts.rounds {
+        stats.ingest()?;
+        thread::sleep(Duration::from_millis(100));
+    }
+    // Scripts should notice an unreachable cluster, not get an empty table
+    if let Some(err) = stats.metadata_error.as_ref() {
+        anyhow::bail!("{}", err);
+    }
+    let mut rows = stats.sorted_basestats();
+    anyhow::ensure!(
+        rows.iter().any(|row| row.rate.is_some()),
+        "No topic has a rate after {} rounds",
+        opts.rounds
+    );
+    rows.extend(stats.aggregate_stats().into_iter().map(|a| a.stats));
+    print_table(&rows);
+    Ok(())
+}
+
+fn print_table(basestats: &[stats::TopicStats]) {
+    let width = basestats
+        .iter()
+        .map(|s| s.topic.name.len())
+        .chain(once("Topic".len()))
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{:width$} {:>7} {:>7} {:>7}",
+        "Topic",
+        "Total",
+        "Seen",
+        "Per Sec",
+        width = width
+    );
+    for stats::TopicStats {
+        topic,
+        total,
+        seen,
+        rate,
+        ..
+    } in basestats
+    {
+        println!(
+            "{:width$} {} {} {}",
+            topic.name,
+            right_align(format_number(*total as f64), 7),
+            right_align(format_number(*seen as f64), 7),
+            right_align(rate.map(format_number).unwrap_or_default(), 7),
+            width = width
+        );
+    }
+}
+
+pub(crate) fn jsonl(mut stats: Stats) -> Result<()> {
+    stats.track_finished();
+    let stdout = io::stdout();
+    loop {
+        stats.ingest()?;
+        let mut out = stdout.lock();
+        for (topic, finished) in stats.take_finished() {
+            let stats::TopicStats {
+                total,
+                seen,
+                rate,
+                deletion_rate,
+                byte_rate,
+                ..
+            } = match stats.current_stats(&topic) {
+                Some(stats) => stats,
+                None => continue,
+            };
+            let timestamp = stats.clock().wall_at(finished);
+            let line = serde_json::json!({
+                "generation": stats.resets(&topic),
+                "topic": topic,
+                "total": total,
+                "seen": seen,
+                "rate": rate,
+                "deletion_rate": deletion_rate,
+                "byte_rate": byte_rate,
+                "timestamp": timestamp.to_rfc3339(),
+            });
+            match writeln!(out, "{}", line).and_then(|()| out.flush()) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                res => res?,
+            }
+        }
+        drop(out);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn once_fails_without_cluster() {
+        let (tx, rx) = mpsc::sync_channel(100);
+        for _ in 0..2 {
+            let err = "Meta data fetch error: BrokerTransportFailure".to_owned();
+            tx.send(scrape::Message::MetadataQueryFail(err)).unwrap();
+            tx.send(scrape::Message::ScrapeFinished {
+                now: Instant::now(),
+            })
+            .unwrap();
+        }
+        let stats = Stats::ingesting(
+            rx,
+            Duration::from_secs(10),
+            Duration::from_secs(3600),
+            Clock::Real,
+        )
+        .unwrap();
+        let opts = Opts::from_iter(["totop", "--demo", "--once"]);
+        let err = table(&opts, stats).unwrap_err();
+        assert!(
+            err.to_string().contains("BrokerTransportFailure"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn once_prints_despite_failing_partition() {
+        let (tx, rx) = mpsc::sync_channel(100);
+        let start = Instant::now();
+        for round in 0..2 {
+            let now = start + Duration::from_secs(10 * round);
+            for partition in 0..3 {
+                tx.send(scrape::Message::PartitionOffsets {
+                    now,
+                    topic: "orders".into(),
+                    partition,
+                    offset: 100 * round as i64,
+                    low: 0,
+                })
+                .unwrap();
+            }
+            tx.send(scrape::Message::RoundFinished {
+                now,
+                topic: "orders".into(),
+                skipped: 1,
+            })
+            .unwrap();
+            // E.g. during a leader election
+            tx.send(scrape::Message::PartitionQueryFail {
+                now,
+                topic: "orders".into(),
+                partition: 3,
+                error: "NotLeaderForPartition".into(),
+            })
+            .unwrap();
+            tx.send(scrape::Message::ScrapeFinished { now }).unwrap();
+        }
+        let stats = Stats::ingesting(
+            rx,
+            Duration::from_secs(10),
+            Duration::from_secs(3600),
+            Clock::Real,
+        )
+        .unwrap();
+        let opts = Opts::from_iter(["totop", "--demo", "--once"]);
+        table(&opts, stats).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter},
+    path::Path,
+};
+
+use crate::{record::Record, scrape::Message, uses::*};
+
+/// Files are rolled over after this fraction of the age or size limit, so pruning doesn't drop too much at once
+const ROLL_PARTS: u32 = 8;
+
+/// Offset samples kept on disk, so a restart doesn't start with an empty chart
+pub struct History {
+    dir: PathBuf,
+    max_age: Duration,
+    max_size: u64,
+}
+
+impl History {
+    pub fn new(dir: &Path, max_age: Duration, max_size: u64) -> Result<Self> {
+        fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+        Ok(History {
+            dir: dir.to_owned(),
+            max_age,
+            max_size,
+        })
+    }
+
+    /// Feed the stored history, then everything from `rx`, while appending the latter to the history
+    pub fn persist(self, rx: Receiver<Message>) -> Result<Receiver<Message>> {
+        self.prune()?;
+        let (tx, fwd) = mpsc::sync_channel(1_000_000);
+        thread::spawn(move || -> Result<()> {
+            tx.send(Message::LoadingHistory)?;
+            self.load(&tx)?;
+            tx.send(Message::HistoryLoaded)?;
+            self.append(rx, &tx)
+        });
+        Ok(fwd)
+    }
+
+    /// History files, oldest first
+    fn files(&self) -> Result<Vec<(PathBuf, fs::Metadata)>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let stamped = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".jsonl"))
+                .map_or(false, |stamp| stamp.parse::<u64>().is_ok());
+            if stamped {
+                files.push((entry.path(), entry.metadata()?));
+            }
+        }
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(files)
+    }
+
+    /// Remove files that are too old, or too much. Never the newest.
+    fn prune(&self) -> Result<()> {
+        let files = self.files()?;
+        let mut size = files.iter().map(|(_, meta)| meta.len()).sum::<u64>();
+        for (path, meta) in files.iter().rev().skip(1).rev() {
+            let old = meta
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .map_or(false, |age| age > self.max_age);
+            if old || size > self.max_size {
+                fs::remove_file(path).context(format!("Failed to remove {}", path.display()))?;
+                size -= meta.len();
+            }
+        }
+        Ok(())
+    }
+
+    fn load(&self, tx: &mpsc::SyncSender<Message>) -> Result<()> {
+        let now = Instant::now();
+        let wall_now = Local::now().timestamp_millis();
+        let oldest = wall_now - self.max_age.as_millis() as i64;
+        // Instants can't represent times before boot, those samples are lost
+        let instant = |ms: i64| match wall_now - ms {
+            ago if ago < 0 => Some(now),
+            ago => now.checked_sub(Duration::from_millis(ago as u64)),
+        };
+        for (path, _) in self.files()? {
+            let lines = BufReader::new(File::open(&path)?).lines();
+            // Ignore anything unreadable, e.g. a line cut short by a crash
+            for line in lines.map_while(Result::ok) {
+                let Record(msg) = match serde_json::from_str(&line) {
+                    Ok(record) => record,
+                    Err(_) => continue,
+                };
+                let at = match msg.time() {
+                    Some(&at) if at >= oldest => instant(at),
+                    _ => None,
+                };
+                if let Some(at) = at {
+                    tx.send(msg.map_time(|_| at))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn append(&self, rx: Receiver<Message>, tx: &mpsc::SyncSender<Message>) -> Result<()> {
+        let mut current: Option<(BufWriter<File>, Instant, u64)> = None;
+        loop {
+            let msg = match rx.try_recv() {
+                Ok(msg) => msg,
+                Err(mpsc::TryRecvError::Empty) => {
+                    if let Some((out, _, _)) = current.as_mut() {
+                        out.flush()?;
+                    }
+                    rx.recv()?
+                }
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+            };
+            if persisted(&msg) {
+                let full = current.as_ref().map_or(true, |(_, opened, written)| {
+                    opened.elapsed() > self.max_age / ROLL_PARTS
+                        || *written > self.max_size / ROLL_PARTS as u64
+                });
+                if full {
+                    if let Some((mut out, _, _)) = current.take() {
+                        out.flush()?;
+                    }
+                    let path = self
+                        .dir
+                        .join(format!("{:013}.jsonl", Local::now().timestamp_millis()));
+                    let file = fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .context(format!("Failed to create {}", path.display()))?;
+                    current = Some((BufWriter::new(file), Instant::now(), 0));
+                    self.prune()?;
+                }
+                if let Some((out, _, written)) = current.as_mut() {
+                    let line = serde_json::to_string(&Record::new(&msg))?;
+                    writeln!(out, "{}", line)?;
+                    *written += line.len() as u64 + 1;
+                }
+            }
+            tx.send(msg)?;
+        }
+    }
+}
+
+/// Only the offset samples are kept, the rest is current state that will be queried again anyway
+fn persisted(msg: &Message) -> bool {
+    matches!(
+        msg,
+        Message::PartitionOffsets { .. }
+            | Message::RoundFinished { .. }
+            | Message::GroupOffsets { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offsets(now: Instant, offset: i64) -> Vec<Message> {
+        let mut msgs = (0..2)
+            .map(|partition| Message::PartitionOffsets {
+                now,
+                topic: "restarted".into(),
+                partition,
+                offset,
+                low: 0,
+            })
+            .collect::<Vec<_>>();
+        msgs.push(Message::RoundFinished {
+            now,
+            topic: "restarted".into(),
+            skipped: 0,
+        });
+        msgs
+    }
+
+    /// Run one session: the history goes in, the given messages are appended
+    fn session(dir: &Path, msgs: Vec<Message>) -> Stats {
+        session_with(dir, msgs, |_| ())
+    }
+
+    fn session_with(dir: &Path, msgs: Vec<Message>, setup: impl FnOnce(&mut Stats)) -> Stats {
+        let (tx, rx) = mpsc::sync_channel(1_000_000);
+        for msg in msgs {
+            tx.send(msg).unwrap();
+        }
+        drop(tx);
+        let history = History::new(dir, Duration::from_secs(3600), 1 << 20).unwrap();
+        let rx = history.persist(rx).unwrap();
+        let (stx, srx) = mpsc::sync_channel(1_000_000);
+        for msg in rx {
+            stx.send(msg).unwrap();
+        }
+        let mut stats = Stats::ingesting(
+            srx,
+            Duration::from_secs(10),
+            Duration::from_secs(3600),
+            Clock::Real,
+        )
+        .unwrap();
+        setup(&mut stats);
+        stats.ingest().unwrap();
+        stats
+    }
+
+    #[test]
+    fn survives_restart() {
+        let dir = std::env::temp_dir().join(format!("totop-history-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let start = Instant::now() - Duration::from_secs(60);
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let first = session(
+            &dir,
+            [0, 10, 20]
+                .into_iter()
+                .flat_map(|s| offsets(at(s), s as i64 * 10))
+                .collect(),
+        );
+        assert_eq!(first.current_stats(&"restarted".into()).unwrap().total, 400);
+
+        // Same topic continues
+        let second = session(&dir, offsets(at(30), 300));
+        let stats = second.current_stats(&"restarted".into()).unwrap();
+        assert_eq!(second.resets(&"restarted".into()), 0);
+        assert_eq!(stats.total, 600);
+        assert_eq!(stats.seen, 600);
+        assert_eq!(second.rounds, 0);
+
+        // Topic was recreated while we were gone
+        let third = session(
+            &dir,
+            [40, 50]
+                .into_iter()
+                .flat_map(|s| offsets(at(s), s as i64 - 30))
+                .collect(),
+        );
+        assert_eq!(third.resets(&"restarted".into()), 1);
+        assert_eq!(third.current_stats(&"restarted".into()).unwrap().total, 40);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn history_raises_no_alerts() {
+        let dir = std::env::temp_dir().join(format!("totop-history-alerts-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let start = Instant::now() - Duration::from_secs(600);
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // Stalled for five minutes, then picked up again, all before the restart
+        let stalled = (0..=30).map(|i| (i * 10, 0));
+        let resumed = (31..=40).map(|i| (i * 10, (i as i64 - 30) * 100));
+        session(
+            &dir,
+            stalled
+                .chain(resumed)
+                .flat_map(|(s, offset)| offsets(at(s), offset))
+                .collect(),
+        );
+
+        let (tx, rx) = mpsc::channel();
+        let stats = session_with(&dir, vec![], |stats| {
+            stats.alert_on(vec!["0 for 1m".parse().unwrap()]);
+            stats.notify_alerts(tx);
+        });
+        assert_eq!(stats.alerts().count(), 0);
+        assert_eq!(rx.try_iter().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
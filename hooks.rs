@@ -0,0 +1,283 @@
+use std::{
+    io::{BufRead, BufReader},
+    net::{TcpStream, ToSocketAddrs},
+    process,
+};
+
+use crate::{
+    alerts::{Alert, AlertEvent},
+    ui::format_number,
+    uses::*,
+};
+
+/// How long a webhook may take to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where alerts go besides the TUI
+pub struct Hooks {
+    /// Write to stderr
+    pub log: bool,
+    pub webhooks: Vec<Webhook>,
+    /// Shell commands
+    pub commands: Vec<String>,
+}
+
+/// Plain http:// only, e.g. a relay running next to totop
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl std::str::FromStr for Webhook {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => anyhow::bail!("Only http:// webhooks are supported, got {}", url),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().context("Bad webhook port")?),
+            None => (authority, 80),
+        };
+        anyhow::ensure!(!host.is_empty(), "No host in webhook {}", url);
+        Ok(Webhook {
+            url: url.to_owned(),
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Webhook {
+    fn post(&self, body: &str) -> Result<()> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .context(format!("Failed to resolve {}", self.host))?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            self.port,
+            body.len(),
+            body
+        )?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => anyhow::bail!("Webhook {} answered {:?}", self.url, status.trim()),
+        }
+    }
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        !self.log && self.webhooks.is_empty() && self.commands.is_empty()
+    }
+
+    /// Deliver alerts sent to the returned channel, one after the other, so a slow hook doesn't hold up the rest of totop.
+    /// Alerts that started firing before this was called are about things long over, and dropped.
+    pub fn spawn(self, clock: Clock) -> mpsc::Sender<AlertEvent> {
+        let (tx, rx) = mpsc::channel::<AlertEvent>();
+        let started = clock.now();
+        thread::spawn(move || {
+            for event in rx {
+                if event.alert().since >= started {
+                    self.deliver(&event, &clock);
+                }
+            }
+        });
+        tx
+    }
+
+    /// Failures are only reported if logging to stderr, the TUI has no place for them
+    fn deliver(&self, event: &AlertEvent, clock: &Clock) {
+        let alert = event.alert();
+        let state = match event {
+            AlertEvent::Firing(_) => "firing",
+            AlertEvent::Resolved(_) => "resolved",
+        };
+        let now = clock.wall_now().format("%Y-%m-%d %H:%M:%S");
+        let rate = alert
+            .rate
+            .map(format_number)
+            .unwrap_or_else(|| "-".to_owned());
+        let text = format!("{} {}: {} (rate {})", state, alert.topic, alert.rule, rate);
+        if self.log {
+            eprintln!("{} {}", now, text);
+        }
+        let failed = |what: &dyn std::fmt::Display, err: anyhow::Error| {
+            if self.log {
+                eprintln!("{} Failed to deliver alert to {}: {:#}", now, what, err);
+            }
+        };
+        if !self.webhooks.is_empty() {
+            let body = payload(state, alert, &text, clock).to_string();
+            for webhook in &self.webhooks {
+                if let Err(err) = webhook.post(&body) {
+                    failed(&webhook.url, err);
+                }
+            }
+        }
+        for command in &self.commands {
+            let status = process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("TOTOP_ALERT_STATE", state)
+                .env("TOTOP_TOPIC", alert.topic.as_str())
+                .env(
+                    "TOTOP_RATE",
+                    alert.rate.map(|r| r.to_string()).unwrap_or_default(),
+                )
+                .env("TOTOP_RULE", alert.rule.to_string())
+                .env("TOTOP_SINCE", clock.wall_at(alert.since).to_rfc3339())
+                .stdin(process::Stdio::null())
+                .status();
+            match status {
+                Ok(status) if status.success() => (),
+                Ok(status) => failed(command, anyhow::anyhow!("{}", status)),
+                Err(err) => failed(command, err.into()),
+            }
+        }
+    }
+}
+
+/// `text` makes it usable as a Slack message as is
+fn payload(state: &str, alert: &Alert, text: &str, clock: &Clock) -> serde_json::Value {
+    serde_json::json!({
+        "state": state,
+        "topic": alert.topic,
+        "rule": alert.rule.to_string(),
+        "rate": alert.rate,
+        "since": clock.wall_at(alert.since).to_rfc3339(),
+        "text": text,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, net::TcpListener};
+
+    fn event(topic: &str) -> AlertEvent {
+        event_since(topic, Instant::now())
+    }
+
+    fn event_since(topic: &str, since: Instant) -> AlertEvent {
+        AlertEvent::Firing(Alert {
+            topic: topic.into(),
+            rule: Arc::new("0 for 2m".parse().unwrap()),
+            rate: Some(0.),
+            since,
+        })
+    }
+
+    #[test]
+    fn posts_to_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks/totop", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(stream, "HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            (request_line, body)
+        });
+
+        let hooks = Hooks {
+            log: false,
+            webhooks: vec![url.parse().unwrap()],
+            commands: vec![],
+        };
+        hooks.deliver(&event("orders"), &Clock::Real);
+
+        let (request_line, body) = server.join().unwrap();
+        assert_eq!(request_line.trim(), "POST /hooks/totop HTTP/1.1");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["state"], "firing");
+        assert_eq!(body["topic"], "orders");
+        assert_eq!(body["rule"], "0 for 2m");
+        assert_eq!(body["rate"], 0.);
+    }
+
+    #[test]
+    fn runs_command() {
+        let out = std::env::temp_dir().join(format!("totop-hook-{}", std::process::id()));
+        let hooks = Hooks {
+            log: false,
+            webhooks: vec![],
+            commands: vec![format!(
+                "echo \"$TOTOP_ALERT_STATE $TOTOP_TOPIC $TOTOP_RATE $TOTOP_RULE\" > {}",
+                out.display()
+            )],
+        };
+        hooks.deliver(&event("payments"), &Clock::Real);
+        let written = std::fs::read_to_string(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        assert_eq!(written.trim(), "firing payments 0 0 for 2m");
+    }
+
+    #[test]
+    fn drops_alerts_from_before_start() {
+        let out = std::env::temp_dir().join(format!("totop-hook-old-{}", std::process::id()));
+        std::fs::remove_file(&out).ok();
+        let hooks = Hooks {
+            log: false,
+            webhooks: vec![],
+            commands: vec![format!("echo $TOTOP_TOPIC >> {}", out.display())],
+        };
+        let before = Instant::now() - Duration::from_secs(3600);
+        let tx = hooks.spawn(Clock::Real);
+        tx.send(event_since("replayed", before)).unwrap();
+        tx.send(event("live")).unwrap();
+        // Hooks run in order, so once the live alert is delivered, the old one would be too
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let written = loop {
+            let written = std::fs::read_to_string(&out).unwrap_or_default();
+            if written.contains("live") || Instant::now() > deadline {
+                break written;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        std::fs::remove_file(&out).ok();
+        assert_eq!(written, "live\n");
+    }
+
+    #[test]
+    fn parses_urls() {
+        let hook = "http://relay:8080".parse::<Webhook>().unwrap();
+        assert_eq!(
+            (hook.host.as_str(), hook.port, hook.path.as_str()),
+            ("relay", 8080, "/")
+        );
+        assert!("https://hooks.slack.com/x".parse::<Webhook>().is_err());
+    }
+}
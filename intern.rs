@@ -0,0 +1,93 @@
+use std::{fmt, hash, ops::Deref};
+
+use crate::uses::*;
+
+/// Interned topic name: cheap to clone, compared and hashed by pointer.
+/// Names are never freed, which is fine as there's only so many topics a cluster ever has.
+#[derive(Clone)]
+pub struct TopicName(Arc<str>);
+
+static INTERNED: Mutex<Option<HashSet<Arc<str>>>> = Mutex::new(None);
+
+impl TopicName {
+    pub fn new(name: &str) -> Self {
+        let mut interned = INTERNED.lock().expect("poisoned");
+        let interned = interned.get_or_insert_with(HashSet::new);
+        match interned.get(name) {
+            Some(name) => TopicName(name.clone()),
+            None => {
+                let name = Arc::<str>::from(name);
+                interned.insert(name.clone());
+                TopicName(name)
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for TopicName {
+    fn from(name: &str) -> Self {
+        TopicName::new(name)
+    }
+}
+
+impl Deref for TopicName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for TopicName {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for TopicName {}
+
+impl hash::Hash for TopicName {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (self.0.as_ptr() as usize).hash(state)
+    }
+}
+
+impl PartialOrd for TopicName {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TopicName {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl fmt::Display for TopicName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+impl fmt::Debug for TopicName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl Serialize for TopicName {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for TopicName {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(TopicName::new(&String::deserialize(deserializer)?))
+    }
+}
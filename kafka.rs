@@ -0,0 +1,256 @@
+use rdkafka::Message as _;
+
+use crate::{
+    scrape::{
+        BrokerInfo, Committed, Metadata, OffsetSource, PartitionError, PartitionInfo, Sample,
+        TopicInfo, Watermarks,
+    },
+    uses::*,
+};
+
+type Client = rdkafka::admin::AdminClient<rdkafka::client::DefaultClientContext>;
+type Consumer = rdkafka::consumer::BaseConsumer;
+
+/// When the sampler gets nothing for this long, it has read all there is.
+/// Transaction markers and compaction can leave the last offsets of a partition without a message to read.
+const SAMPLE_IDLE: Duration = Duration::from_secs(1);
+
+/// Offsets from an actual Kafka cluster
+pub struct Kafka {
+    client: Client,
+    consumer: Consumer,
+    config: KafkaConfig,
+    /// One consumer per group, as the committed offsets are always queried for the consumer's own group.id.
+    /// They never subscribe, so they don't join (and disturb) the group.
+    /// Each is a full client with its own threads and connections, so they are kept until the group is gone.
+    group_consumers: Mutex<HashMap<String, Consumer>>,
+    /// Reads messages for size estimates, by assignment, without a group
+    sampler: Mutex<Consumer>,
+}
+
+impl Kafka {
+    pub fn new(opts: &Opts) -> Result<Self> {
+        let mut config = KafkaConfig::new();
+        config.set(
+            "bootstrap.servers",
+            opts.brokers.as_deref().unwrap_or_default(),
+        );
+        for (k, v) in &opts.kafka_options {
+            config.set(k, v);
+        }
+        Ok(Kafka {
+            client: config.create().context("Failed to construct client")?,
+            consumer: config.create().context("Failed to construct consumer")?,
+            sampler: Mutex::new(
+                config
+                    .clone()
+                    .set("enable.auto.commit", "false")
+                    .set("enable.auto.offset.store", "false")
+                    .create()
+                    .context("Failed to construct sampling consumer")?,
+            ),
+            config,
+            group_consumers: Mutex::default(),
+        })
+    }
+}
+
+impl OffsetSource for Kafka {
+    fn metadata(&self, timeout: Duration) -> Result<Metadata> {
+        let metadata = self.client.inner().fetch_metadata(None, timeout)?;
+        Ok(Metadata {
+            brokers: metadata
+                .brokers()
+                .iter()
+                .map(|b| BrokerInfo {
+                    id: b.id(),
+                    host: format!("{}:{}", b.host(), b.port()),
+                })
+                .collect(),
+            topics: metadata
+                .topics()
+                .iter()
+                .map(|topic| TopicInfo {
+                    name: TopicName::new(topic.name()),
+                    partitions: topic
+                        .partitions()
+                        .iter()
+                        .map(|p| PartitionInfo {
+                            id: p.id(),
+                            leader: p.leader(),
+                            isr: p.isr().to_vec(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+
+    fn watermarks(
+        &self,
+        partitions: &[(&str, i32)],
+        timeout: Duration,
+    ) -> Result<(Instant, Vec<Watermarks>, Vec<PartitionError>)> {
+        // ListOffsets with the special timestamps for earliest and latest
+        let query = |offset| {
+            let mut tpl = TopicPartitionList::with_capacity(partitions.len());
+            for &(topic, partition) in partitions {
+                tpl.add_partition_offset(topic, partition, offset)?;
+            }
+            self.consumer.offsets_for_times(tpl, timeout)
+        };
+        let highs = query(Offset::End)?;
+        let now = Instant::now();
+        let lows = query(Offset::Beginning)?;
+        let low_errors = lows
+            .elements()
+            .iter()
+            .filter_map(|elem| {
+                let err = elem.error().err()?;
+                Some(((elem.topic().to_owned(), elem.partition()), err.to_string()))
+            })
+            .collect::<HashMap<_, _>>();
+        let lows = lows.to_topic_map();
+        let mut watermarks = Vec::new();
+        let mut errors = Vec::new();
+        for elem in highs.elements() {
+            let topic = elem.topic();
+            let partition = elem.partition();
+            let low = lows.get(&(topic.into(), partition));
+            match (elem.error(), elem.offset(), low) {
+                (Ok(()), Offset::Offset(high), Some(Offset::Offset(low))) => {
+                    watermarks.push(Watermarks {
+                        topic: TopicName::new(topic),
+                        partition,
+                        low: *low,
+                        high,
+                    })
+                }
+                (result, high, low) => errors.push(PartitionError {
+                    topic: TopicName::new(topic),
+                    partition,
+                    error: match (result, low_errors.get(&(topic.to_owned(), partition))) {
+                        (Err(err), _) => err.to_string(),
+                        (_, Some(err)) => err.clone(),
+                        _ => format!("Unexpected offsets {:?} to {:?}", low, high),
+                    },
+                }),
+            }
+        }
+        Ok((now, watermarks, errors))
+    }
+
+    fn groups(&self, timeout: Duration) -> Result<Vec<String>> {
+        let groups = self.client.inner().fetch_group_list(None, timeout)?;
+        let groups = groups
+            .groups()
+            .iter()
+            .filter(|group| group.protocol_type() == "consumer")
+            .map(|group| group.name().to_owned())
+            .collect::<Vec<_>>();
+        self.group_consumers
+            .lock()
+            .expect("poisoned")
+            .retain(|group, _| groups.contains(group));
+        Ok(groups)
+    }
+
+    fn committed(
+        &self,
+        group: &str,
+        partitions: &[(&str, i32)],
+        timeout: Duration,
+    ) -> Result<(Instant, Vec<Committed>)> {
+        let mut consumers = self.group_consumers.lock().expect("poisoned");
+        let consumer = match consumers.entry(group.to_owned()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                self.config
+                    .clone()
+                    .set("group.id", group)
+                    .set("enable.auto.commit", "false")
+                    .create()
+                    .context("Failed to construct group consumer")?,
+            ),
+        };
+        let mut tpl = TopicPartitionList::with_capacity(partitions.len());
+        for &(topic, partition) in partitions {
+            tpl.add_partition(topic, partition);
+        }
+        let committed = consumer.committed_offsets(tpl, timeout)?;
+        let now = Instant::now();
+        let committed = committed
+            .elements()
+            .iter()
+            .filter_map(|elem| match elem.offset() {
+                Offset::Offset(offset) => Some(Committed {
+                    topic: TopicName::new(elem.topic()),
+                    partition: elem.partition(),
+                    offset,
+                }),
+                _ => None,
+            })
+            .collect();
+        Ok((now, committed))
+    }
+
+    fn sample(&self, ranges: &[(&str, i32, i64, i64)], timeout: Duration) -> Result<Vec<Sample>> {
+        let sampler = self.sampler.lock().expect("poisoned");
+        let mut tpl = TopicPartitionList::with_capacity(ranges.len());
+        let mut samples = HashMap::new();
+        for &(topic, partition, from, to) in ranges {
+            tpl.add_partition_offset(topic, partition, Offset::Offset(from))?;
+            let sample = Sample {
+                topic: TopicName::new(topic),
+                partition,
+                messages: 0,
+                bytes: 0,
+                newest_timestamp: None,
+            };
+            samples.insert((sample.topic.clone(), partition), (to, sample));
+        }
+        sampler.assign(&tpl)?;
+        let deadline = Instant::now() + timeout;
+        let mut pending = samples.keys().cloned().collect::<HashSet<_>>();
+        let mut failed = None;
+        while !pending.is_empty() {
+            let left = match deadline.checked_duration_since(Instant::now()) {
+                Some(left) => left,
+                None => break,
+            };
+            let message = match sampler.poll(cmp::min(left, SAMPLE_IDLE)) {
+                Some(Ok(message)) => message,
+                Some(Err(err)) => {
+                    failed = Some(err);
+                    break;
+                }
+                None => break,
+            };
+            let key = (TopicName::new(message.topic()), message.partition());
+            if let Some((to, sample)) = samples.get_mut(&key) {
+                if message.offset() < *to {
+                    sample.messages += 1;
+                    sample.bytes += (message.key_len() + message.payload_len()) as u64;
+                    // CreateTime or LogAppendTime, whichever the topic is configured for
+                    if let Some(timestamp) = message.timestamp().to_millis() {
+                        sample.newest_timestamp =
+                            cmp::max(sample.newest_timestamp, Some(timestamp));
+                    }
+                }
+                // Offsets can have gaps, so the end is wherever the first message at or past it is
+                if message.offset() + 1 >= *to {
+                    pending.remove(&key);
+                }
+            }
+        }
+        sampler.assign(&TopicPartitionList::new())?;
+        if let Some(err) = failed {
+            return Err(err).context("Failed to sample messages");
+        }
+        Ok(samples
+            .into_values()
+            .map(|(_, sample)| sample)
+            .filter(|sample| sample.messages > 0)
+            .collect())
+    }
+}
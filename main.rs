@@ -1,6 +1,19 @@
+pub mod alerts;
+pub mod check;
+pub mod clock;
 pub mod colors;
+pub mod grouping;
+pub mod headless;
+pub mod history;
+pub mod hooks;
+pub mod intern;
+pub mod kafka;
+pub mod record;
 pub mod scrape;
+pub mod series;
+pub mod serve;
 pub mod stats;
+pub mod synthetic;
 pub mod ui;
 pub mod uses;
 
@@ -12,8 +25,8 @@ use uses::*;
 #[structopt(version = "0.1", author = "Julius Michaelis")]
 pub struct Opts {
     /// Bootstrap broker address
-    #[structopt(short, long)]
-    brokers: String,
+    #[structopt(short, long, required_unless_one = &["replay", "demo"])]
+    brokers: Option<String>,
     /// Additional kafka client options
     #[structopt(short = "X", long, parse(try_from_str = parseopts))]
     kafka_options: Vec<(String, String)>,
@@ -21,6 +34,9 @@ pub struct Opts {
     /// Length of history to draw as graph
     #[structopt(short, long, default_value = "15 min", parse(try_from_str = parsehuman))]
     draw_interval: Duration,
+    /// Length of history to keep, for zooming out. At least the draw interval
+    #[structopt(short, long, default_value = "1 h", parse(try_from_str = parsehuman))]
+    retention: Duration,
 
     /// Polling interval
     #[structopt(short, long, default_value = "10 s", parse(try_from_str = parsehuman))]
@@ -28,6 +44,141 @@ pub struct Opts {
     /// Metadata retrieval timeout
     #[structopt(short = "T", long, default_value = "5 s", parse(try_from_str = parsehuman))]
     scrape_timeout: Duration,
+
+    /// Print the topic table to stdout and exit instead of starting the TUI
+    #[structopt(long, visible_alias = "print", conflicts_with = "output")]
+    once: bool,
+    /// Number of scrapes to wait for before printing, at least 2
+    #[structopt(long, default_value = "2")]
+    rounds: usize,
+
+    /// Output format: tui, or jsonl for one JSON object per topic and scrape round on stdout
+    #[structopt(short, long, default_value = "tui")]
+    output: Output,
+
+    /// Only query topics matching this regex (repeatable)
+    #[structopt(short = "i", long, number_of_values = 1)]
+    include: Vec<Regex>,
+    /// Don't query topics matching this regex (repeatable, takes precedence over --include)
+    #[structopt(short = "e", long, number_of_values = 1)]
+    exclude: Vec<Regex>,
+    /// Also query internal topics, like __consumer_offsets
+    #[structopt(long)]
+    internal: bool,
+
+    /// Combine topics by their first N dot-separated segments, e.g. 2 for team.domain.*
+    #[structopt(long)]
+    group_depth: Option<usize>,
+    /// Combine topics by the capture named `group` of this regex, the first one, or the whole match (repeatable, before --group-depth)
+    #[structopt(long, number_of_values = 1)]
+    group_regex: Vec<Regex>,
+
+    /// Estimate message sizes this often, by reading the newest few messages of each partition
+    #[structopt(long, parse(try_from_str = parsehuman))]
+    sample_interval: Option<Duration>,
+    /// Number of messages per partition to read for size estimates
+    #[structopt(long, default_value = "10")]
+    sample_messages: i64,
+
+    /// Read the newest message of each partition every scrape, to tell how old it is
+    #[structopt(long)]
+    freshness: bool,
+    /// Flag topics whose newest message is older than this
+    #[structopt(long, default_value = "15 min", parse(try_from_str = parsehuman))]
+    stale_after: Duration,
+
+    /// Alert when a topic stalls: "RATE for DURATION" if its average rate stays at or below RATE msgs/s,
+    /// "PERCENT% of DURATION" if its rate drops below that share of its average.
+    /// Prefix with "REGEX: " to only apply to some topics (repeatable)
+    #[structopt(long, number_of_values = 1)]
+    stall: Vec<alerts::StallRule>,
+    /// Write alerts to stderr when not running the TUI
+    #[structopt(long)]
+    log_alerts: bool,
+    /// POST alerts as JSON to this http:// URL (repeatable)
+    #[structopt(long, number_of_values = 1, conflicts_with = "replay")]
+    webhook: Vec<hooks::Webhook>,
+    /// Run this shell command on alerts, with TOTOP_ALERT_STATE, TOTOP_TOPIC, TOTOP_RATE, TOTOP_RULE
+    /// and TOTOP_SINCE set (repeatable)
+    #[structopt(long, number_of_values = 1, conflicts_with = "replay")]
+    alert_command: Vec<String>,
+
+    /// Also track lag and consume rate of consumer groups
+    #[structopt(short = "g", long)]
+    consumer_groups: bool,
+
+    /// Append all scrape results to this file, for later --replay
+    #[structopt(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+    /// Show a recording instead of querying Kafka
+    #[structopt(long)]
+    replay: Option<PathBuf>,
+    /// Replay this many times faster than real time
+    #[structopt(long, default_value = "1")]
+    replay_speed: f64,
+    /// Skip this far into the recording
+    #[structopt(long, default_value = "0s", parse(try_from_str = parsehuman))]
+    replay_seek: Duration,
+
+    /// Keep offsets in this directory, and load them on startup
+    #[structopt(long, conflicts_with = "replay")]
+    history_dir: Option<PathBuf>,
+    /// Discard history older than this
+    #[structopt(long, default_value = "1 day", parse(try_from_str = parsehuman))]
+    history_max_age: Duration,
+    /// Keep at most this many MiB of history
+    #[structopt(long, default_value = "100")]
+    history_max_mib: u64,
+
+    /// Show a made-up cluster instead of querying Kafka
+    #[structopt(long, conflicts_with = "replay")]
+    demo: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Run as a Prometheus exporter instead of showing the TUI
+    Serve {
+        /// Address to serve /metrics on
+        #[structopt(short, long, default_value = "0.0.0.0:9464")]
+        listen: std::net::SocketAddr,
+    },
+    /// Exit with 0 (OK), 1 (WARN) or 2 (CRIT) depending on whether topics produce fast enough, for monitoring and smoke tests
+    Check {
+        /// Topic name or glob pattern, optionally with its own thresholds as PATTERN:MIN_RATE:WARN_RATE (repeatable)
+        #[structopt(long = "topic", required = true, number_of_values = 1)]
+        topics: Vec<check::Assertion>,
+        /// CRIT if a topic produces fewer messages per second
+        #[structopt(long)]
+        min_rate: Option<f64>,
+        /// WARN if a topic produces fewer messages per second
+        #[structopt(long)]
+        warn_rate: Option<f64>,
+        /// Wait at most this long for the rates to be reached
+        #[structopt(long, default_value = "1 min", parse(try_from_str = parsehuman))]
+        within: Duration,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Tui,
+    Jsonl,
+}
+
+impl std::str::FromStr for Output {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tui" => Ok(Output::Tui),
+            "jsonl" => Ok(Output::Jsonl),
+            _ => anyhow::bail!("Unknown output format {}, expected tui or jsonl", s),
+        }
+    }
 }
 
 fn parseopts(arg: &str) -> Result<(String, String)> {
@@ -48,8 +199,69 @@ fn parsehuman(arg: &str) -> Result<Duration> {
 
 fn main() -> Result<()> {
     let opts = Opts::from_args();
-    let scrape = scrape::spawn_threads(&opts);
-    let stats = Stats::ingesting(scrape?, opts.scrape_interval)?;
+    let (scrape, clock) = match &opts.replay {
+        Some(path) => {
+            anyhow::ensure!(opts.replay_speed > 0., "Replay speed must be positive");
+            let (scrape, clock) = record::replay(path, opts.replay_speed, opts.replay_seek)?;
+            (scrape, Clock::Replay(clock))
+        }
+        None => {
+            let source: Arc<dyn scrape::OffsetSource> = match opts.demo {
+                true => Arc::new(synthetic::Synthetic::demo(Clock::Real)),
+                false => Arc::new(kafka::Kafka::new(&opts)?),
+            };
+            (scrape::spawn_threads(&opts, source), Clock::Real)
+        }
+    };
+    let scrape = match &opts.record {
+        Some(path) => record::record(scrape, path)?,
+        None => scrape,
+    };
+    let scrape = match &opts.history_dir {
+        Some(dir) => history::History::new(dir, opts.history_max_age, opts.history_max_mib << 20)?
+            .persist(scrape)?,
+        None => scrape,
+    };
+    let mut stats = Stats::ingesting(
+        scrape,
+        opts.scrape_interval,
+        cmp::max(opts.retention, opts.draw_interval),
+        clock,
+    )?;
+    stats.group_by(Grouping::new(opts.group_regex.clone(), opts.group_depth));
+    stats.alert_on(opts.stall.clone());
+    let headless = opts.command.is_some() || opts.once || opts.output == Output::Jsonl;
+    let hooks = hooks::Hooks {
+        log: opts.log_alerts && headless,
+        webhooks: opts.webhook.clone(),
+        commands: opts.alert_command.clone(),
+    };
+    if !hooks.is_empty() {
+        stats.notify_alerts(hooks.spawn(stats.clock().clone()));
+    }
+    match &opts.command {
+        Some(Command::Serve { listen }) => return serve::run(*listen, stats),
+        Some(Command::Check {
+            topics,
+            min_rate,
+            warn_rate,
+            within,
+        }) => {
+            let status = check::run(&opts, topics, *min_rate, *warn_rate, *within, stats)
+                .unwrap_or_else(|err| {
+                    println!("{} - {:#}", check::Status::Crit, err);
+                    check::Status::Crit
+                });
+            std::process::exit(status as i32);
+        }
+        None => (),
+    }
+    if opts.once {
+        return headless::table(&opts, stats);
+    }
+    if opts.output == Output::Jsonl {
+        return headless::jsonl(stats);
+    }
     std::panic::set_hook(Box::new(move |info| {
         disable_raw_mode().ok();
         better_panic::Settings::new().create_panic_handler()(info);
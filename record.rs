@@ -0,0 +1,222 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use chrono::TimeZone;
+
+use crate::clock::{Clock, ReplayClock};
+use crate::uses::*;
+
+/// One line in a recording, with times as milliseconds since the epoch.
+/// Messages without a time of their own are replayed right after the one before them.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Record(pub scrape::Message<i64>);
+
+impl Record {
+    pub fn new(msg: &scrape::Message) -> Self {
+        let clock = Clock::Real;
+        Record(
+            msg.clone()
+                .map_time(|instant| clock.wall_at(instant).timestamp_millis()),
+        )
+    }
+}
+
+/// Append everything that passes through to a file
+pub fn record(rx: Receiver<scrape::Message>, path: &Path) -> Result<Receiver<scrape::Message>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("Failed to open {} for recording", path.display()))?;
+    let (tx, fwd) = mpsc::sync_channel(1_000_000);
+    thread::spawn(move || -> Result<()> {
+        let mut out = BufWriter::new(file);
+        loop {
+            let msg = match rx.try_recv() {
+                Ok(msg) => msg,
+                Err(mpsc::TryRecvError::Empty) => {
+                    out.flush()?;
+                    rx.recv()?
+                }
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+            };
+            serde_json::to_writer(&mut out, &Record::new(&msg))?;
+            writeln!(out)?;
+            tx.send(msg)?;
+        }
+    });
+    Ok(fwd)
+}
+
+/// Feed the messages from a recording at the pace of the returned clock
+pub fn replay(
+    path: &Path,
+    speed: f64,
+    seek: Duration,
+) -> Result<(Receiver<scrape::Message>, Arc<ReplayClock>)> {
+    let mut first = None;
+    for line in
+        BufReader::new(File::open(path).context(format!("Failed to open {}", path.display()))?)
+            .lines()
+    {
+        let Record(msg) = serde_json::from_str(&line?).context("Not a recording")?;
+        if let Some(&at) = msg.time() {
+            first = Some(at);
+            break;
+        }
+    }
+    let first = first.context("Empty recording")?;
+    let clock = Arc::new(ReplayClock::new(from_ms(first)?, speed));
+    clock.seek(seek, false);
+    let (tx, rx) = mpsc::sync_channel(1_000_000);
+    thread::spawn({
+        let path = path.to_owned();
+        let clock = clock.clone();
+        move || replay_loop(path, tx, clock)
+    });
+    Ok((rx, clock))
+}
+
+fn replay_loop(
+    path: PathBuf,
+    tx: mpsc::SyncSender<scrape::Message>,
+    clock: Arc<ReplayClock>,
+) -> Result<()> {
+    'pass: loop {
+        let rewinds = clock.rewinds();
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let Record(msg) = serde_json::from_str(&line?)?;
+            let at = match msg.time() {
+                Some(&at) => Some(clock.instant_at(from_ms(at)?)),
+                None => None,
+            };
+            while let Some(at) = at {
+                if clock.rewinds() != rewinds {
+                    tx.send(scrape::Message::Reset)?;
+                    continue 'pass;
+                }
+                let now = clock.now();
+                match at.checked_duration_since(now) {
+                    None => break,
+                    Some(ahead) => thread::sleep(cmp::min(
+                        ahead.div_f64(clock.speed()),
+                        Duration::from_millis(100),
+                    )),
+                }
+            }
+            tx.send(msg.map_time(|_| at.expect("has a time")))?;
+        }
+        // End of recording, wait for a rewind
+        while clock.rewinds() == rewinds {
+            thread::sleep(Duration::from_millis(100));
+        }
+        tx.send(scrape::Message::Reset)?;
+    }
+}
+
+fn from_ms(ms: i64) -> Result<DateTime<Local>> {
+    Local
+        .timestamp_millis_opt(ms)
+        .single()
+        .context(format!("Invalid timestamp {}", ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::SimClock,
+        scrape::{query_offsets, State},
+        synthetic::{Synthetic, SyntheticTopic},
+    };
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+    const ROUNDS: usize = 6;
+
+    fn ingest_all(stats: &mut Stats) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while stats.rounds < ROUNDS && Instant::now() < deadline {
+            stats.ingest().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn summary(stats: &Stats) -> Vec<(String, i64, i64, f64)> {
+        stats
+            .sorted_basestats()
+            .into_iter()
+            .map(|s| {
+                let rate = s.rate.unwrap_or(f64::NAN);
+                (s.topic.name.to_string(), s.total, s.retained, rate)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_recording() {
+        let path = std::env::temp_dir().join(format!("totop-record-{}", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let clock = Arc::new(SimClock::new(Local::now()));
+        let topic = |name: &str, partitions, rate| SyntheticTopic {
+            name: name.into(),
+            partitions,
+            rate,
+            ..SyntheticTopic::default()
+        };
+        let source = Synthetic::new(
+            Clock::Sim(clock.clone()),
+            2,
+            vec![topic("orders", 3, 6.), topic("audit", 1, 0.5)],
+            vec![],
+        );
+        let state = State::new(&Opts::from_iter(["totop", "--demo"]));
+        let (tx, rx) = mpsc::sync_channel(10_000);
+        for _ in 0..ROUNDS {
+            query_offsets(&state, &tx, &source).unwrap();
+            clock.advance(INTERVAL);
+        }
+        drop(tx);
+        // Everything has been written once the recorder stops forwarding
+        let recorded = record(rx, &path).unwrap().into_iter().collect::<Vec<_>>();
+
+        let (tx, rx) = mpsc::sync_channel(10_000);
+        for msg in recorded {
+            tx.send(msg).unwrap();
+        }
+        let mut live = Stats::ingesting(rx, INTERVAL, INTERVAL * 100, Clock::Sim(clock)).unwrap();
+        ingest_all(&mut live);
+
+        let (rx, replay_clock) = replay(&path, 1000., Duration::ZERO).unwrap();
+        let mut replayed =
+            Stats::ingesting(rx, INTERVAL, INTERVAL * 100, Clock::Replay(replay_clock)).unwrap();
+        ingest_all(&mut replayed);
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(replayed.rounds, ROUNDS);
+        let (live, replayed) = (summary(&live), summary(&replayed));
+        assert_eq!(live.len(), 2);
+        for (a, b) in live.iter().zip(&replayed) {
+            assert_eq!((&a.0, a.1, a.2), (&b.0, b.1, b.2));
+            // Times are stored to the millisecond
+            assert!((a.3 - b.3).abs() < 1e-3 * a.3, "{:?} vs {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn records_unit_variants() {
+        let line = serde_json::to_string(&Record::new(&scrape::Message::Reset)).unwrap();
+        assert_eq!(line, "\"Reset\"");
+        let Record(msg) = serde_json::from_str(&line).unwrap();
+        assert!(matches!(msg, scrape::Message::Reset));
+        // One timestamp per message
+        let msg = scrape::Message::ScrapeFinished {
+            now: Instant::now(),
+        };
+        let line = serde_json::to_string(&Record::new(&msg)).unwrap();
+        assert_eq!(line.matches(char::is_numeric).count(), 13, "{}", line);
+    }
+}
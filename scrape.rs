@@ -1,56 +1,287 @@
 use crate::uses::*;
 
-#[derive(Debug)]
-pub enum Message {
-    MetadataQueryFail(KafkaError),
-    BrokerQueryFail(String),
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartitionInfo {
+    pub id: i32,
+    pub leader: i32,
+    pub isr: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrokerInfo {
+    pub id: i32,
+    /// host:port
+    pub host: String,
+}
+
+/// Generic over the time type so recordings can carry wall clock timestamps instead of [Instant]s
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message<T = Instant> {
+    MetadataQueryFail(String),
+    /// Reading the newest messages failed, for sizes or freshness
+    SampleQueryFail(String),
+    Brokers(Vec<BrokerInfo>),
+    BrokerQueryOk {
+        now: T,
+        broker: i32,
+    },
+    BrokerQueryFail {
+        now: T,
+        broker: i32,
+        error: String,
+    },
     PartitionOffsets {
-        now: Instant,
-        topic: String, // TODO: intern
+        now: T,
+        topic: TopicName,
         partition: i32,
         offset: i64,
+        low: i64,
     },
     RoundFinished {
-        now: Instant,
-        topic: String,
+        now: T,
+        topic: TopicName,
+        /// Partitions that weren't queried because their leader is bad, or that returned an error
+        skipped: usize,
+    },
+    TopicMetadata {
+        topic: TopicName,
+        partitions: Vec<PartitionInfo>,
+    },
+    /// All topics have been queried once
+    ScrapeFinished {
+        now: T,
+    },
+    GroupOffsets {
+        now: T,
+        group: String,
+        topic: TopicName,
+        partition: i32,
+        offset: i64,
+    },
+    GroupRoundFinished {
+        now: T,
+        group: String,
+    },
+    /// Size of a few of the newest messages of a partition
+    SampledSizes {
+        now: T,
+        topic: TopicName,
+        partition: i32,
+        messages: i64,
+        bytes: u64,
+    },
+    /// Kafka timestamp of the newest message of a partition, in ms since the epoch
+    NewestMessage {
+        now: T,
+        topic: TopicName,
+        partition: i32,
+        timestamp: i64,
     },
+    /// A partition whose leader answered, but not about that partition
+    PartitionQueryFail {
+        now: T,
+        topic: TopicName,
+        partition: i32,
+        error: String,
+    },
+    /// Forget everything, e.g. because a replay was rewound
+    Reset,
+    /// Samples from the history directory follow, until [HistoryLoaded](Self::HistoryLoaded)
+    LoadingHistory,
+    HistoryLoaded,
 }
 
-struct QueryTask<'a> {
-    when: Instant,
-    topic: &'a str,
-    partitions: &'a [MetadataPartition],
-}
+impl<T> Message<T> {
+    pub fn map_time<U>(self, f: impl FnOnce(T) -> U) -> Message<U> {
+        use Message::*;
+        match self {
+            MetadataQueryFail(err) => MetadataQueryFail(err),
+            SampleQueryFail(err) => SampleQueryFail(err),
+            Brokers(brokers) => Brokers(brokers),
+            BrokerQueryOk { now, broker } => BrokerQueryOk {
+                now: f(now),
+                broker,
+            },
+            BrokerQueryFail { now, broker, error } => BrokerQueryFail {
+                now: f(now),
+                broker,
+                error,
+            },
+            PartitionOffsets {
+                now,
+                topic,
+                partition,
+                offset,
+                low,
+            } => PartitionOffsets {
+                now: f(now),
+                topic,
+                partition,
+                offset,
+                low,
+            },
+            RoundFinished {
+                now,
+                topic,
+                skipped,
+            } => RoundFinished {
+                now: f(now),
+                topic,
+                skipped,
+            },
+            TopicMetadata { topic, partitions } => TopicMetadata { topic, partitions },
+            ScrapeFinished { now } => ScrapeFinished { now: f(now) },
+            GroupOffsets {
+                now,
+                group,
+                topic,
+                partition,
+                offset,
+            } => GroupOffsets {
+                now: f(now),
+                group,
+                topic,
+                partition,
+                offset,
+            },
+            GroupRoundFinished { now, group } => GroupRoundFinished { now: f(now), group },
+            SampledSizes {
+                now,
+                topic,
+                partition,
+                messages,
+                bytes,
+            } => SampledSizes {
+                now: f(now),
+                topic,
+                partition,
+                messages,
+                bytes,
+            },
+            NewestMessage {
+                now,
+                topic,
+                partition,
+                timestamp,
+            } => NewestMessage {
+                now: f(now),
+                topic,
+                partition,
+                timestamp,
+            },
+            PartitionQueryFail {
+                now,
+                topic,
+                partition,
+                error,
+            } => PartitionQueryFail {
+                now: f(now),
+                topic,
+                partition,
+                error,
+            },
+            Reset => Reset,
+            LoadingHistory => LoadingHistory,
+            HistoryLoaded => HistoryLoaded,
+        }
+    }
 
-impl PartialEq for QueryTask<'_> {
-    fn eq(&self, other: &Self) -> bool {
-        self.when == other.when
-            && self.topic == other.topic
-            && self.partitions.len() == other.partitions.len()
-            && self
-                .partitions
-                .iter()
-                .zip(other.partitions.iter())
-                .all(|(selb, other)| {
-                    selb.id() == other.id()
-                        && selb.isr() == other.isr()
-                        && selb.leader() == other.leader()
-                        && selb.replicas() == other.replicas()
-                })
+    pub fn time(&self) -> Option<&T> {
+        use Message::*;
+        match self {
+            BrokerQueryOk { now, .. }
+            | BrokerQueryFail { now, .. }
+            | PartitionOffsets { now, .. }
+            | RoundFinished { now, .. }
+            | ScrapeFinished { now }
+            | GroupOffsets { now, .. }
+            | GroupRoundFinished { now, .. }
+            | SampledSizes { now, .. }
+            | NewestMessage { now, .. }
+            | PartitionQueryFail { now, .. } => Some(now),
+            MetadataQueryFail(_)
+            | SampleQueryFail(_)
+            | Brokers(_)
+            | TopicMetadata { .. }
+            | Reset
+            | LoadingHistory
+            | HistoryLoaded => None,
+        }
     }
 }
 
-impl Eq for QueryTask<'_> {}
+/// Brokers and topics of a cluster
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub brokers: Vec<BrokerInfo>,
+    pub topics: Vec<TopicInfo>,
+}
 
-impl PartialOrd for QueryTask<'_> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
+#[derive(Debug, Clone)]
+pub struct TopicInfo {
+    pub name: TopicName,
+    pub partitions: Vec<PartitionInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watermarks {
+    pub topic: TopicName,
+    pub partition: i32,
+    pub low: i64,
+    pub high: i64,
+}
+
+/// A partition that couldn't be queried, though its leader answered
+#[derive(Debug, Clone)]
+pub struct PartitionError {
+    pub topic: TopicName,
+    pub partition: i32,
+    pub error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Committed {
+    pub topic: TopicName,
+    pub partition: i32,
+    pub offset: i64,
 }
 
-impl Ord for QueryTask<'_> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.when.cmp(&other.when).then(self.topic.cmp(other.topic))
+/// Messages read back from a partition
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub topic: TopicName,
+    pub partition: i32,
+    pub messages: i64,
+    /// Keys and payloads
+    pub bytes: u64,
+    /// Kafka timestamp of the newest message read, in ms since the epoch
+    pub newest_timestamp: Option<i64>,
+}
+
+/// Where the scrapers get their offsets from: a Kafka cluster, or something pretending to be one
+pub trait OffsetSource: Send + Sync {
+    fn metadata(&self, timeout: Duration) -> Result<Metadata>;
+    /// Low and high watermarks of a batch of partitions led by the same broker,
+    /// and when the high watermarks were current. Partitions that failed individually are listed separately.
+    fn watermarks(
+        &self,
+        partitions: &[(&str, i32)],
+        timeout: Duration,
+    ) -> Result<(Instant, Vec<Watermarks>, Vec<PartitionError>)>;
+    /// Names of consumer groups
+    fn groups(&self, timeout: Duration) -> Result<Vec<String>>;
+    /// Offsets a group has committed on the given partitions, and when they were queried
+    fn committed(
+        &self,
+        group: &str,
+        partitions: &[(&str, i32)],
+        timeout: Duration,
+    ) -> Result<(Instant, Vec<Committed>)>;
+    /// Read the messages between two offsets of each partition, and tell how large they were.
+    /// Partitions that are slow to deliver are cut short or left out.
+    fn sample(&self, ranges: &[(&str, i32, i64, i64)], timeout: Duration) -> Result<Vec<Sample>>;
+    fn now(&self) -> Instant {
+        Instant::now()
     }
 }
 
@@ -59,169 +290,436 @@ pub struct State {
     bad_brokers: Mutex<HashSet<i32>>,
     query_interval: Duration,
     query_timeout: Duration,
+    filter: TopicFilter,
+    /// Latest low and high watermarks, for the sampler
+    watermarks: Mutex<HashMap<(TopicName, i32), (i64, i64)>>,
+    /// How many of the newest messages of each partition to sample
+    sample_messages: i64,
+}
+
+impl State {
+    pub fn new(opts: &Opts) -> Self {
+        State {
+            query_interval: opts.scrape_interval,
+            query_timeout: opts.scrape_timeout,
+            filter: TopicFilter {
+                include: opts.include.clone(),
+                exclude: opts.exclude.clone(),
+                internal: opts.internal,
+            },
+            sample_messages: opts.sample_messages,
+            ..State::default()
+        }
+    }
 }
 
-type Client = rdkafka::admin::AdminClient<rdkafka::client::DefaultClientContext>;
+/// Topics Kafka itself keeps. librdkafka's metadata doesn't say which topics are internal,
+/// and plenty of ordinary topics start with underscores too.
+const INTERNAL_TOPICS: &[&str] = &["__consumer_offsets", "__transaction_state"];
 
-pub fn spawn_threads(opts: &Opts) -> Result<Receiver<Message>> {
-    let state = Arc::new(State {
-        query_interval: opts.scrape_interval,
-        query_timeout: opts.scrape_timeout,
-        ..State::default()
-    });
+/// Which topics to query offsets for
+#[derive(Default)]
+struct TopicFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    internal: bool,
+}
+
+impl TopicFilter {
+    fn matches(&self, topic: &str) -> bool {
+        (self.internal || !INTERNAL_TOPICS.contains(&topic))
+            && (self.include.is_empty() || self.include.iter().any(|re| re.is_match(topic)))
+            && !self.exclude.iter().any(|re| re.is_match(topic))
+    }
+}
+
+pub fn spawn_threads(opts: &Opts, source: Arc<dyn OffsetSource>) -> Receiver<Message> {
+    let state = Arc::new(State::new(opts));
     let (offtx, offrx) = mpsc::sync_channel(1_000_000);
     thread::spawn({
         let state = state.clone();
-        let client = client(opts)?;
-        || query_offsets(state, offtx, client)
+        let offtx = offtx.clone();
+        let source = source.clone();
+        move || {
+            every(state.query_interval, || {
+                query_offsets(&state, &offtx, &*source)
+            })
+        }
     });
     thread::spawn({
         let state = state.clone();
-        let client = client(opts)?;
-        || query_bad(state, client)
+        let offtx = offtx.clone();
+        let source = source.clone();
+        move || every(state.query_interval, || query_bad(&state, &offtx, &*source))
     });
-    Ok(offrx)
-}
-
-fn client(opts: &Opts) -> Result<Client> {
-    let mut config = KafkaConfig::new();
-    config.set("bootstrap.servers", &opts.brokers);
-    for (k, v) in &opts.kafka_options {
-        config.set(k, v);
+    if let Some(interval) = opts.sample_interval {
+        thread::spawn({
+            let state = state.clone();
+            let offtx = offtx.clone();
+            let source = source.clone();
+            move || every(interval, || query_sizes(&state, &offtx, &*source))
+        });
     }
-    let client: rdkafka::admin::AdminClient<_> =
-        config.create().context("Failed to construct client")?;
-    Ok(client)
+    if opts.freshness {
+        thread::spawn({
+            let state = state.clone();
+            let offtx = offtx.clone();
+            let source = source.clone();
+            move || {
+                every(state.query_interval, || {
+                    query_freshness(&state, &offtx, &*source)
+                })
+            }
+        });
+    }
+    if opts.consumer_groups {
+        thread::spawn({
+            let state = state.clone();
+            move || {
+                every(state.query_interval, || {
+                    query_groups(&state, &offtx, &*source)
+                })
+            }
+        });
+    }
+    offrx
 }
 
-fn query_offsets(state: Arc<State>, tx: mpsc::SyncSender<Message>, client: Client) -> Result<()> {
+/// Run `round` every `interval`, or back to back if it takes longer than that
+fn every(interval: Duration, mut round: impl FnMut() -> Result<()>) -> Result<()> {
     let mut next = Instant::now();
-    let client = client.inner();
     loop {
-        next += state.query_interval;
-
-        let mut query_tasks = BTreeSet::new(); // Better structures exist for scheduling.
-        let metadata = client.fetch_metadata(None, state.query_timeout);
-        match metadata.as_ref() {
-            Ok(metadata) => {
-                let now = Instant::now();
-                for topic in metadata.topics() {
-                    let partitions = topic.partitions();
-                    let offset = state
-                        .query_interval
-                        .mul_f64(rand_seeder::SipHasher::from(topic.name()).into_rng().gen());
-                    query_tasks.insert(QueryTask {
-                        when: now + offset,
-                        topic: topic.name().into(),
-                        partitions,
-                    });
-                }
-            }
-            Err(err) => tx.send(Message::MetadataQueryFail(err.clone()))?,
+        next += interval;
+        round()?;
+        let now = Instant::now();
+        if let Some(sleep) = next.checked_duration_since(now) {
+            thread::sleep(sleep);
+        } else {
+            next = now;
         }
+    }
+}
 
-        let mut bads = state.bad_brokers.lock().expect("poisoned").clone();
-        let mut newbads = Vec::new();
-        for QueryTask {
-            when,
-            topic,
-            partitions,
-        } in query_tasks.range(..)
-        {
-            for partition in partitions.iter() {
-                if bads.contains(&partition.leader()) {
+/// One round of querying the offsets of all partitions whose leaders aren't known to be bad
+pub fn query_offsets(
+    state: &State,
+    tx: &mpsc::SyncSender<Message>,
+    source: &dyn OffsetSource,
+) -> Result<()> {
+    let bads = state.bad_brokers.lock().expect("poisoned").clone();
+    let mut topics = HashMap::new();
+    let mut by_leader = HashMap::<i32, Vec<(&str, i32)>>::new();
+    let metadata = source.metadata(state.query_timeout);
+    match metadata.as_ref() {
+        Ok(metadata) => {
+            tx.send(Message::Brokers(metadata.brokers.clone()))?;
+            for topic in &metadata.topics {
+                if !state.filter.matches(&topic.name) {
                     continue;
                 }
-                if let Some(sleep) = when.checked_duration_since(Instant::now()) {
-                    thread::sleep(sleep);
+                tx.send(Message::TopicMetadata {
+                    topic: topic.name.clone(),
+                    partitions: topic.partitions.clone(),
+                })?;
+                topics.insert(topic.name.clone(), topic.partitions.len());
+                for partition in &topic.partitions {
+                    // Leaderless partitions can't be queried, they count as skipped until they get one
+                    if partition.leader < 0 || bads.contains(&partition.leader) {
+                        continue;
+                    }
+                    by_leader
+                        .entry(partition.leader)
+                        .or_default()
+                        .push((topic.name.as_str(), partition.id));
                 }
-                match client.fetch_watermarks(topic, partition.id(), state.query_timeout) {
-                    Ok((_low, high)) => tx.send(Message::PartitionOffsets {
-                        now: Instant::now(),
-                        topic: (*topic).into(),
-                        partition: partition.id(),
-                        offset: high,
-                    })?,
+            }
+        }
+        Err(err) => tx.send(Message::MetadataQueryFail(err.to_string()))?,
+    }
 
-                    Err(_) => {
-                        bads.insert(partition.leader());
-                        newbads.push(partition.leader())
+    // One request per broker, all brokers at once, so a slow broker only holds up its own partitions
+    let results = thread::scope(|s| {
+        let queries = by_leader
+            .iter()
+            .map(|(&leader, partitions)| {
+                let query = s.spawn(|| source.watermarks(partitions, state.query_timeout));
+                (leader, query)
+            })
+            .collect::<Vec<_>>();
+        queries
+            .into_iter()
+            .map(|(leader, query)| (leader, query.join().expect("Offset query panicked")))
+            .collect::<Vec<_>>()
+    });
+    let mut newbads = Vec::new();
+    let mut failed = Vec::new();
+    for (leader, result) in results {
+        match result {
+            Ok((now, watermarks, errors)) => {
+                failed.extend(errors);
+                tx.send(Message::BrokerQueryOk {
+                    now,
+                    broker: leader,
+                })?;
+                for Watermarks {
+                    topic,
+                    partition,
+                    low,
+                    high,
+                } in watermarks
+                {
+                    if let Some(skipped) = topics.get_mut(&topic) {
+                        *skipped -= 1;
                     }
-                };
+                    state
+                        .watermarks
+                        .lock()
+                        .expect("poisoned")
+                        .insert((topic.clone(), partition), (low, high));
+                    tx.send(Message::PartitionOffsets {
+                        now,
+                        topic,
+                        partition,
+                        offset: high,
+                        low,
+                    })?;
+                }
             }
-            tx.send(Message::RoundFinished {
-                now: Instant::now(),
-                topic: (*topic).into(),
-            })?;
-        }
-        {
-            let mut bads = state.bad_brokers.lock().expect("poisoned");
-            for bad in newbads {
-                bads.insert(bad);
+            Err(err) => {
+                newbads.push(leader);
+                tx.send(Message::BrokerQueryFail {
+                    now: source.now(),
+                    broker: leader,
+                    error: err.to_string(),
+                })?;
             }
         }
-
-        let now = Instant::now();
-        if let Some(sleep) = next.checked_duration_since(now) {
-            thread::sleep(sleep);
-        } else {
-            next = now;
-        }
     }
-}
-
-fn query_bad(state: Arc<State>, client: Client) -> Result<()> {
-    let mut next = Instant::now();
-    let client = client.inner();
-    loop {
-        next += state.query_interval;
-
-        let mut bads = state
-            .bad_brokers
+    if metadata.is_ok() {
+        // Deleted topics, or ones filtered out, are not sampled any more
+        state
+            .watermarks
             .lock()
             .expect("poisoned")
-            .drain()
-            .map(|k| (k, false))
-            .collect::<HashMap<_, _>>();
-        let mut nowgood = HashSet::new();
-        match client.fetch_metadata(None, state.query_interval) {
-            Ok(metadata) => {
-                for topic in metadata.topics() {
-                    for partition in topic.partitions() {
-                        let leader = partition.leader();
-                        match bads.entry(leader) {
-                            Entry::Vacant(_) => (),                       // not bad
-                            Entry::Occupied(entry) if *entry.get() => (), // already queried
-                            Entry::Occupied(mut entry) => {
-                                match client.fetch_watermarks(
-                                    &topic.name(),
-                                    partition.id(),
-                                    state.query_interval,
-                                ) {
-                                    Ok(_) => {
-                                        entry.remove();
-                                        nowgood.insert(leader)
-                                    }
-                                    Err(_) => entry.insert(true),
-                                };
+            .retain(|(topic, _), _| topics.contains_key(topic));
+    }
+    let now = source.now();
+    for (topic, skipped) in topics {
+        tx.send(Message::RoundFinished {
+            now,
+            topic,
+            skipped,
+        })?;
+    }
+    for PartitionError {
+        topic,
+        partition,
+        error,
+    } in failed
+    {
+        tx.send(Message::PartitionQueryFail {
+            now,
+            topic,
+            partition,
+            error,
+        })?;
+    }
+    state.bad_brokers.lock().expect("poisoned").extend(newbads);
+    tx.send(Message::ScrapeFinished { now })?;
+    Ok(())
+}
+
+/// One round of checking whether bad brokers answer again
+pub fn query_bad(
+    state: &State,
+    tx: &mpsc::SyncSender<Message>,
+    source: &dyn OffsetSource,
+) -> Result<()> {
+    let mut bads = state
+        .bad_brokers
+        .lock()
+        .expect("poisoned")
+        .iter()
+        .map(|&k| (k, false))
+        .collect::<HashMap<_, _>>();
+    let mut nowgood = HashSet::new();
+    if let Ok(metadata) = source.metadata(state.query_interval) {
+        for topic in &metadata.topics {
+            for partition in &topic.partitions {
+                let leader = partition.leader;
+                match bads.entry(leader) {
+                    Entry::Vacant(_) => (),                       // not bad
+                    Entry::Occupied(entry) if *entry.get() => (), // already queried
+                    Entry::Occupied(mut entry) => {
+                        match source.watermarks(
+                            &[(topic.name.as_str(), partition.id)],
+                            state.query_interval,
+                        ) {
+                            Ok((now, watermarks, _)) if !watermarks.is_empty() => {
+                                entry.remove();
+                                tx.send(Message::BrokerQueryOk {
+                                    now,
+                                    broker: leader,
+                                })?;
+                                nowgood.insert(leader)
                             }
-                        }
+                            Ok(_) | Err(_) => entry.insert(true),
+                        };
                     }
                 }
             }
-            Err(_) => (),
         }
-        state
-            .bad_brokers
-            .lock()
-            .expect("poisoned")
-            .retain(|wasbad| !nowgood.contains(wasbad));
+    }
+    state
+        .bad_brokers
+        .lock()
+        .expect("poisoned")
+        .retain(|wasbad| !nowgood.contains(wasbad));
+    Ok(())
+}
 
-        let now = Instant::now();
-        if let Some(sleep) = next.checked_duration_since(now) {
-            thread::sleep(sleep);
-        } else {
-            next = now;
+/// One round of querying the committed offsets of all consumer groups
+pub fn query_groups(
+    state: &State,
+    tx: &mpsc::SyncSender<Message>,
+    source: &dyn OffsetSource,
+) -> Result<()> {
+    let groups = source.groups(state.query_timeout);
+    let metadata = source.metadata(state.query_timeout);
+    match (groups, metadata) {
+        (Ok(groups), Ok(metadata)) => {
+            let partitions = metadata
+                .topics
+                .iter()
+                .filter(|topic| state.filter.matches(&topic.name))
+                .flat_map(|topic| {
+                    topic
+                        .partitions
+                        .iter()
+                        .map(|partition| (topic.name.as_str(), partition.id))
+                })
+                .collect::<Vec<_>>();
+            for group in groups {
+                let (now, committed) =
+                    match source.committed(&group, &partitions, state.query_timeout) {
+                        Ok(committed) => committed,
+                        Err(_) => continue, // Coordinator probably moving, try again next round
+                    };
+                for Committed {
+                    topic,
+                    partition,
+                    offset,
+                } in committed
+                {
+                    tx.send(Message::GroupOffsets {
+                        now,
+                        group: group.clone(),
+                        topic,
+                        partition,
+                        offset,
+                    })?;
+                }
+                tx.send(Message::GroupRoundFinished { now, group })?;
+            }
         }
+        (Err(err), _) | (_, Err(err)) => tx.send(Message::MetadataQueryFail(err.to_string()))?,
+    }
+    Ok(())
+}
+
+/// One round of reading the newest few messages of each partition, to estimate message sizes
+pub fn query_sizes(
+    state: &State,
+    tx: &mpsc::SyncSender<Message>,
+    source: &dyn OffsetSource,
+) -> Result<()> {
+    let now = source.now();
+    let samples = match sample_newest(state, source, state.sample_messages) {
+        Ok(samples) => samples,
+        Err(err) => {
+            tx.send(Message::SampleQueryFail(format!("{:#}", err)))?;
+            return Ok(());
+        }
+    };
+    for sample in samples {
+        tx.send(Message::SampledSizes {
+            now,
+            topic: sample.topic,
+            partition: sample.partition,
+            messages: sample.messages,
+            bytes: sample.bytes,
+        })?;
+    }
+    Ok(())
+}
+
+/// One round of reading the newest message of each partition, to tell how old it is
+pub fn query_freshness(
+    state: &State,
+    tx: &mpsc::SyncSender<Message>,
+    source: &dyn OffsetSource,
+) -> Result<()> {
+    let now = source.now();
+    let samples = match sample_newest(state, source, 1) {
+        Ok(samples) => samples,
+        Err(err) => {
+            tx.send(Message::SampleQueryFail(format!("{:#}", err)))?;
+            return Ok(());
+        }
+    };
+    for sample in samples {
+        if let Some(timestamp) = sample.newest_timestamp {
+            tx.send(Message::NewestMessage {
+                now,
+                topic: sample.topic,
+                partition: sample.partition,
+                timestamp,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Read up to `count` of the newest messages of each partition, as of the last offset query
+fn sample_newest(state: &State, source: &dyn OffsetSource, count: i64) -> Result<Vec<Sample>> {
+    let watermarks = state.watermarks.lock().expect("poisoned").clone();
+    let ranges = watermarks
+        .iter()
+        .filter(|(_, (low, high))| high > low)
+        .map(|((topic, partition), &(low, high))| {
+            (
+                topic.as_str(),
+                *partition,
+                cmp::max(low, high - count),
+                high,
+            )
+        })
+        .collect::<Vec<_>>();
+    if ranges.is_empty() {
+        return Ok(Vec::new());
+    }
+    source.sample(&ranges, state.query_timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_internal_topics_by_name() {
+        let filter = TopicFilter {
+            exclude: vec![Regex::new("^tmp-").unwrap()],
+            ..TopicFilter::default()
+        };
+        assert!(!filter.matches("__consumer_offsets"));
+        assert!(!filter.matches("__transaction_state"));
+        assert!(filter.matches("__connect-offsets"));
+        assert!(filter.matches("orders"));
+        assert!(!filter.matches("tmp-orders"));
+        let filter = TopicFilter {
+            internal: true,
+            ..filter
+        };
+        assert!(filter.matches("__consumer_offsets"));
     }
 }
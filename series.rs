@@ -0,0 +1,169 @@
+use std::ops::Bound;
+
+use crate::uses::*;
+
+/// Resolution and how long to keep samples, relative to the newest sample, of each tier
+const TIERS: [(Duration, Duration); 3] = [
+    (Duration::ZERO, Duration::from_secs(3600)),
+    (Duration::from_secs(60), Duration::from_secs(24 * 3600)),
+    (Duration::from_secs(3600), Duration::MAX),
+];
+
+/// Samples of an offset, all of the recent ones, then one per minute for a day, then one per hour.
+/// As offsets only grow, thinning them out keeps the average rates between the remaining samples right.
+#[derive(Default, Debug)]
+pub struct Series {
+    tiers: [BTreeMap<Instant, i64>; 3],
+}
+
+impl Series {
+    pub fn insert(&mut self, at: Instant, offset: i64) {
+        for (tier, (resolution, _)) in self.tiers.iter_mut().zip(TIERS) {
+            let due = match tier.keys().next_back() {
+                Some(&last) => at
+                    .checked_duration_since(last)
+                    .map_or(false, |d| d >= resolution),
+                None => true,
+            };
+            if due || resolution.is_zero() {
+                tier.insert(at, offset);
+            }
+        }
+    }
+
+    /// Latest offset
+    pub fn last(&self) -> Option<i64> {
+        self.tiers[0].values().next_back().copied()
+    }
+
+    /// Latest sample at or before `at`
+    pub fn at(&self, at: Instant) -> Option<(Instant, i64)> {
+        self.tiers
+            .iter()
+            .filter_map(|tier| tier.range(..=at).next_back())
+            .max_by_key(|(&at, _)| at)
+            .map(|(&at, &offset)| (at, offset))
+    }
+
+    /// Oldest remaining sample
+    pub fn first(&self) -> Option<(Instant, i64)> {
+        self.tiers
+            .iter()
+            .filter_map(|tier| tier.iter().next())
+            .min_by_key(|(&at, _)| at)
+            .map(|(&at, &offset)| (at, offset))
+    }
+
+    /// All samples that haven't been thinned out yet, at least the last hour
+    pub fn recent(&self) -> &BTreeMap<Instant, i64> {
+        &self.tiers[0]
+    }
+
+    /// Samples at no coarser than `resolution`, walking as few as possible:
+    /// From the coarsest tier that's fine enough, plus older ones from coarser and newer ones from finer tiers.
+    pub fn view(&self, resolution: Duration) -> impl Iterator<Item = (&Instant, &i64)> {
+        let chosen = TIERS
+            .iter()
+            .rposition(|&(tier_resolution, _)| tier_resolution <= resolution)
+            .unwrap_or(0);
+        let first = |tier: usize| self.tiers[tier].keys().next().copied();
+        let last = |tier: usize| self.tiers[tier].keys().next_back().copied();
+        let bounded = |bound: Option<Instant>, older: bool| match (bound, older) {
+            (Some(bound), true) => (Bound::Unbounded, Bound::Excluded(bound)),
+            (Some(bound), false) => (Bound::Excluded(bound), Bound::Unbounded),
+            (None, _) => (Bound::Unbounded, Bound::Unbounded),
+        };
+        // Coarser tiers only contribute what's older, finer ones only what's newer than everything so far
+        let mut older = Vec::new();
+        let mut oldest = first(chosen);
+        for tier in chosen + 1..self.tiers.len() {
+            older.push(self.tiers[tier].range(bounded(oldest, true)));
+            oldest = oldest.into_iter().chain(first(tier)).min();
+        }
+        let mut newer = Vec::new();
+        let mut newest = (chosen..self.tiers.len()).filter_map(last).max();
+        for tier in (0..chosen).rev() {
+            newer.push(self.tiers[tier].range(bounded(newest, false)));
+            newest = newest.into_iter().chain(last(tier)).max();
+        }
+        older
+            .into_iter()
+            .rev()
+            .chain(once(self.tiers[chosen].range(..)))
+            .chain(newer)
+            .flatten()
+    }
+
+    /// Drop samples older than `discard`, and those that the tiers don't need to keep anymore
+    pub fn discard_before(&mut self, discard: Option<Instant>) {
+        let newest = match self.tiers[0].keys().next_back() {
+            Some(&newest) => newest,
+            None => return,
+        };
+        for (tier, (_, keep)) in self.tiers.iter_mut().zip(TIERS) {
+            if let Some(discard) = cmp::max(newest.checked_sub(keep), discard) {
+                *tier = tier.split_off(&discard);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tiers.iter_mut().for_each(BTreeMap::clear);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One sample every 10 s for two days, at 1/s
+    fn two_days() -> (Instant, Series) {
+        let start = Instant::now();
+        let mut series = Series::default();
+        for i in 0..(2 * 24 * 360) {
+            series.insert(start + Duration::from_secs(i * 10), i as i64 * 10);
+        }
+        (start, series)
+    }
+
+    #[test]
+    fn tiers_are_thinned() {
+        let (start, mut series) = two_days();
+        let newest = start + Duration::from_secs(2 * 24 * 3600 - 10);
+        series.discard_before(None);
+        assert_eq!(series.recent().len(), 361);
+        assert_eq!(series.tiers[1].len(), 24 * 60);
+        assert_eq!(series.tiers[2].len(), 48);
+        assert_eq!(series.first(), Some((start, 0)));
+        assert_eq!(series.last(), Some(2 * 24 * 3600 - 10));
+        // Retention cuts through all tiers
+        series.discard_before(Some(newest - Duration::from_secs(1800)));
+        assert_eq!(series.recent().len(), 181);
+        assert_eq!(series.tiers[1].len(), 30);
+        assert!(series.tiers[2].is_empty());
+        assert_eq!(series.view(Duration::from_secs(3600)).count(), 30 + 5);
+    }
+
+    #[test]
+    fn views_are_ordered_and_complete() {
+        let (start, mut series) = two_days();
+        series.discard_before(None);
+        for (resolution, samples) in [
+            (0, 24 + 1380 + 361),
+            (60, 24 + 1440 + 5),
+            (3600, 48 + 59 + 5),
+        ] {
+            let view = series
+                .view(Duration::from_secs(resolution))
+                .collect::<Vec<_>>();
+            assert!(view.windows(2).all(|w| w[0].0 < w[1].0));
+            assert_eq!(view.first().map(|(&at, _)| at), Some(start));
+            assert_eq!(view.last().map(|(_, &o)| o), series.last());
+            // Every sample is still a sample of the same counter
+            assert!(view
+                .iter()
+                .all(|(&at, &offset)| offset as u64 == (at - start).as_secs()));
+            assert_eq!(view.len(), samples, "{}", resolution);
+        }
+    }
+}
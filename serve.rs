@@ -0,0 +1,381 @@
+use std::fmt::Write as _;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use crate::uses::*;
+
+pub(crate) fn run(listen: SocketAddr, stats: Stats) -> Result<()> {
+    let listener = TcpListener::bind(listen).context(format!("Failed to bind {}", listen))?;
+    serve(listener, stats)
+}
+
+fn serve(listener: TcpListener, mut stats: Stats) -> Result<()> {
+    let metrics = Arc::new(Mutex::new(render(&stats)));
+    thread::spawn({
+        let metrics = metrics.clone();
+        move || {
+            for stream in listener.incoming().flatten() {
+                // Scrapers are patient, but one hanging client shouldn't block all others
+                stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
+                let metrics = metrics.clone();
+                thread::spawn(move || respond(stream, &metrics).ok());
+            }
+        }
+    });
+    loop {
+        if stats.ingest()? {
+            *metrics.lock().expect("poisoned") = render(&stats);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<String>) -> Result<()> {
+    let mut request = io::BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    io::BufRead::read_line(&mut request, &mut request_line)?;
+    // Skip the headers, nothing in there is of interest
+    loop {
+        let mut header = String::new();
+        if io::BufRead::read_line(&mut request, &mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            metrics.lock().expect("poisoned").clone(),
+        ),
+        (Some("GET"), Some("/")) => (
+            "200 OK",
+            "text/html",
+            "<a href=\"/metrics\">Metrics</a>\n".into(),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not found\n".into()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n".into(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+fn render(stats: &Stats) -> String {
+    let mut out = String::new();
+    let basestats = stats
+        .basestats()
+        .filter(|s| s.topic.stat_idx == 0)
+        .collect::<Vec<_>>();
+
+    header(
+        &mut out,
+        "totop_topic_high_watermark",
+        "gauge",
+        "Sum of the high watermarks of all partitions of a topic",
+    );
+    for s in &basestats {
+        writeln!(
+            out,
+            "totop_topic_high_watermark{{topic=\"{}\"}} {}",
+            escape(&s.topic.name),
+            s.total
+        )
+        .ok();
+    }
+    header(
+        &mut out,
+        "totop_topic_messages_per_second",
+        "gauge",
+        "Rate of messages produced to a topic between the last two scrapes",
+    );
+    for s in &basestats {
+        if let Some(rate) = s.rate {
+            writeln!(
+                out,
+                "totop_topic_messages_per_second{{topic=\"{}\"}} {}",
+                escape(&s.topic.name),
+                rate
+            )
+            .ok();
+        }
+    }
+    header(
+        &mut out,
+        "totop_topic_bytes_per_second",
+        "gauge",
+        "Rate of messages produced to a topic times their average sampled size",
+    );
+    for s in &basestats {
+        if let Some(byte_rate) = s.byte_rate {
+            writeln!(
+                out,
+                "totop_topic_bytes_per_second{{topic=\"{}\"}} {}",
+                escape(&s.topic.name),
+                byte_rate
+            )
+            .ok();
+        }
+    }
+    header(
+        &mut out,
+        "totop_topic_newest_message_age_seconds",
+        "gauge",
+        "Time since the timestamp of the newest message of a topic, with --freshness",
+    );
+    for s in &basestats {
+        if let Some(age) = stats.message_age(s) {
+            writeln!(
+                out,
+                "totop_topic_newest_message_age_seconds{{topic=\"{}\"}} {}",
+                escape(&s.topic.name),
+                age.as_secs_f64()
+            )
+            .ok();
+        }
+    }
+    header(
+        &mut out,
+        "totop_topic_stalled",
+        "gauge",
+        "1 for each --stall rule that currently holds for a topic",
+    );
+    for alert in stats.alerts() {
+        writeln!(
+            out,
+            "totop_topic_stalled{{topic=\"{}\",rule=\"{}\"}} 1",
+            escape(&alert.topic),
+            escape(&alert.rule.to_string())
+        )
+        .ok();
+    }
+    header(
+        &mut out,
+        "totop_topic_retained_messages",
+        "gauge",
+        "Difference between high and low watermarks, summed over all partitions",
+    );
+    for s in &basestats {
+        writeln!(
+            out,
+            "totop_topic_retained_messages{{topic=\"{}\"}} {}",
+            escape(&s.topic.name),
+            s.retained
+        )
+        .ok();
+    }
+    header(
+        &mut out,
+        "totop_topic_deleted_messages_per_second",
+        "gauge",
+        "Rate at which retention removes messages from a topic, from the low watermarks",
+    );
+    for s in &basestats {
+        if let Some(deletion_rate) = s.deletion_rate {
+            writeln!(
+                out,
+                "totop_topic_deleted_messages_per_second{{topic=\"{}\"}} {}",
+                escape(&s.topic.name),
+                deletion_rate
+            )
+            .ok();
+        }
+    }
+    header(
+        &mut out,
+        "totop_partition_high_watermark",
+        "gauge",
+        "High watermark of a partition",
+    );
+    for (topic, partition, offset) in stats.partition_offsets() {
+        writeln!(
+            out,
+            "totop_partition_high_watermark{{topic=\"{}\",partition=\"{}\"}} {}",
+            escape(topic),
+            partition,
+            offset
+        )
+        .ok();
+    }
+    header(
+        &mut out,
+        "totop_group_lag",
+        "gauge",
+        "Messages not yet committed by a consumer group, summed over all partitions of a topic",
+    );
+    for s in &basestats {
+        for g in &s.groups {
+            writeln!(
+                out,
+                "totop_group_lag{{topic=\"{}\",group=\"{}\"}} {}",
+                escape(&s.topic.name),
+                escape(&g.group),
+                g.lag
+            )
+            .ok();
+        }
+    }
+
+    header(
+        &mut out,
+        "totop_broker_up",
+        "gauge",
+        "Whether the broker answered the last offset query",
+    );
+    for b in stats.broker_stats() {
+        writeln!(
+            out,
+            "totop_broker_up{{broker=\"{}\",host=\"{}\"}} {}",
+            b.id,
+            escape(b.host.as_deref().unwrap_or_default()),
+            b.down.is_none() as u8
+        )
+        .ok();
+    }
+    header(
+        &mut out,
+        "totop_scrape_rounds_total",
+        "counter",
+        "Finished scrapes over all topics",
+    );
+    writeln!(out, "totop_scrape_rounds_total {}", stats.rounds).ok();
+    header(
+        &mut out,
+        "totop_metadata_failures_total",
+        "counter",
+        "Failed cluster metadata queries",
+    );
+    writeln!(
+        out,
+        "totop_metadata_failures_total {}",
+        stats.metadata_failures
+    )
+    .ok();
+    header(
+        &mut out,
+        "totop_broker_failures_total",
+        "counter",
+        "Partition leaders that failed to answer an offset query and were marked bad",
+    );
+    writeln!(out, "totop_broker_failures_total {}", stats.broker_failures).ok();
+    header(
+        &mut out,
+        "totop_sample_failures_total",
+        "counter",
+        "Failed attempts to read the newest messages, for sizes or freshness",
+    );
+    writeln!(out, "totop_sample_failures_total {}", stats.sample_failures).ok();
+    header(
+        &mut out,
+        "totop_partition_failures_total",
+        "counter",
+        "Partitions that failed to be queried while their leader answered",
+    );
+    writeln!(
+        out,
+        "totop_partition_failures_total {}",
+        stats.partition_failures
+    )
+    .ok();
+    header(
+        &mut out,
+        "totop_partitions_failing",
+        "gauge",
+        "Partitions whose last offset query failed",
+    );
+    writeln!(
+        out,
+        "totop_partitions_failing {}",
+        stats.partition_errors.len()
+    )
+    .ok();
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).ok();
+    writeln!(out, "# TYPE {} {}", name, kind).ok();
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::SimClock,
+        scrape::{query_offsets, State},
+        synthetic::{Synthetic, SyntheticTopic},
+    };
+    use std::io::Read;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: totop\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let clock = Arc::new(SimClock::new(Local::now()));
+        let topic = SyntheticTopic {
+            name: "orders".into(),
+            partitions: 2,
+            rate: 6.,
+            ..SyntheticTopic::default()
+        };
+        let source = Synthetic::new(Clock::Sim(clock.clone()), 1, vec![topic], vec![]);
+        let state = State::new(&Opts::from_iter(["totop", "--demo"]));
+        let (tx, rx) = mpsc::sync_channel(1000);
+        for _ in 0..2 {
+            query_offsets(&state, &tx, &source).unwrap();
+            clock.advance(Duration::from_secs(10));
+        }
+        let stats = Stats::ingesting(
+            rx,
+            Duration::from_secs(10),
+            Duration::from_secs(3600),
+            Clock::Sim(clock),
+        )
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, stats));
+
+        // Rendered before anything is ingested at first, so wait for the topic to show up
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let response = loop {
+            let response = get(addr, "/metrics");
+            if response.contains("topic=\"orders\"") || Instant::now() > deadline {
+                break response;
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        assert!(response.contains(
+            "# HELP totop_topic_messages_per_second Rate of messages produced to a topic between the last two scrapes\n\
+             # TYPE totop_topic_messages_per_second gauge\n\
+             totop_topic_messages_per_second{topic=\"orders\"} 6\n"
+        ));
+        assert!(response.contains("totop_topic_high_watermark{topic=\"orders\"} 60\n"));
+        assert!(get(addr, "/nope").starts_with("HTTP/1.1 404 Not Found"));
+        drop(tx);
+    }
+}
@@ -1,26 +1,92 @@
-use crate::uses::*;
+use crate::{
+    alerts::{Alert, AlertEvent, Condition, StallRule},
+    uses::*,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Topic {
-    pub name: String,
+    pub name: TopicName,
     // The idea here is that I often test stuff in docker containers, and tend to delete those and start afresh quite often
     // That results in several unrelated instances of a topic with the same name.
     pub stat_idx: usize,
 }
 
 pub struct Stats {
-    data: HashMap<String, Vec<TopicData>>,
+    data: HashMap<TopicName, Vec<TopicData>>,
+    metadata: HashMap<TopicName, Vec<scrape::PartitionInfo>>,
+    brokers: BTreeMap<i32, BrokerState>,
     offrx: Receiver<scrape::Message>,
     scrape_interval: Duration, // This will get more complicated, with per-topic, variable intervals
-    pub metadata_error: Option<KafkaError>,
+    /// How long to keep offsets, independent of how much is drawn
+    retention: Duration,
+    last_discard: Instant,
+    clock: Clock,
+    pub metadata_error: Option<String>,
+    /// Number of finished scrapes over all topics
+    pub rounds: usize,
+    pub metadata_failures: u64,
+    /// Number of times a partition leader was found unresponsive
+    pub broker_failures: u64,
+    /// Partitions whose leader answered, but not about them, until they are queried successfully
+    pub partition_errors: HashMap<(TopicName, i32), String>,
+    /// Number of times a single partition failed to be queried
+    pub partition_failures: u64,
+    /// Why the last attempt to read messages failed, until one succeeds
+    pub sample_error: Option<String>,
+    pub sample_failures: u64,
+    /// Topics that finished a round since the last call to [take_finished](Self::take_finished), if requested,
+    /// and when they last did
+    finished: Option<HashMap<TopicName, Instant>>,
+    grouping: Grouping,
+    /// Aggregate of each topic seen so far, according to `grouping`
+    grouped: HashMap<TopicName, Option<TopicName>>,
+    aggregates: HashSet<TopicName>,
+    rules: Vec<Arc<StallRule>>,
+    /// Firing alerts, by topic and index of the rule
+    alerts: BTreeMap<(TopicName, usize), Alert>,
+    /// Where to send alerts that start or stop firing
+    alert_tx: Option<mpsc::Sender<AlertEvent>>,
+    /// Stored samples are being loaded, alerts raised from them would be long over
+    loading_history: bool,
+    /// Number of old generations dropped by [discard_before](Self::discard_before), by topic
+    forgotten: HashMap<TopicName, usize>,
 }
 
+type Polls = HashMap<i32, Series>;
+
 #[derive(Default, Debug)]
 pub struct TopicData {
-    partitions: HashMap<i32, BTreeMap<Instant, i64>>,
+    partitions: Polls,
+    /// Low watermarks
+    lows: Polls,
+    /// Committed offsets by consumer group
+    groups: HashMap<String, Polls>,
     scraped_interval: Option<(Instant, Instant)>,
     decreased: usize,
     scraped: usize,
+    /// Partitions not scraped in the last round
+    skipped: usize,
+    /// Latest sampled number of messages and their bytes, by partition
+    sizes: HashMap<i32, (i64, u64)>,
+    /// Kafka timestamp of the newest message, by partition
+    newest: HashMap<i32, i64>,
+}
+
+/// What rates count
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Unit {
+    #[default]
+    Messages,
+    /// Estimated from sampled message sizes
+    Bytes,
+}
+
+#[derive(Default, Debug)]
+struct BrokerState {
+    host: Option<String>,
+    last_ok: Option<Instant>,
+    /// Since when and why the broker is considered bad
+    failed: Option<(Instant, String)>,
 }
 
 #[derive(Debug)]
@@ -33,21 +99,139 @@ pub struct TopicStats {
     pub seen: i64,
     /// seen / scrape_interval
     pub rate: Option<f64>,
+    /// Sum of differences between high and low watermarks
+    pub retained: i64,
+    /// Messages per second removed by retention
+    pub deletion_rate: Option<f64>,
+    /// Consumer groups that committed offsets on this topic
+    pub groups: Vec<GroupStats>,
+    /// Partitions that could not be scraped in the last round, usually because their leader is down
+    pub skipped: usize,
+    /// Average size of sampled messages
+    pub message_size: Option<f64>,
+    /// rate * message_size
+    pub byte_rate: Option<f64>,
+    /// Kafka timestamp of the newest message in any partition, in ms since the epoch
+    pub newest_message: Option<i64>,
+}
+
+/// Sums over the current generations of a group of topics
+#[derive(Debug)]
+pub struct AggregateStats {
+    /// Stands in for the group wherever a topic would: in the table, the chart, or for colors.
+    /// Consumer groups are summed by name.
+    pub stats: TopicStats,
+    pub members: Vec<TopicStats>,
+}
+
+/// Summary of the whole cluster
+#[derive(Debug)]
+pub struct ClusterStats {
+    /// Sum of the rates of the current generation of all topics
+    pub rate: Option<f64>,
+    /// Sum of the byte rates of those topics that have sampled sizes
+    pub byte_rate: Option<f64>,
+    pub topics: usize,
+    pub partitions: usize,
+    pub brokers: usize,
+    /// End of the latest successful scrape round of any topic
+    pub last_scrape: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct GroupStats {
+    pub group: String,
+    /// Sum of differences between high watermark and committed offset
+    pub lag: i64,
+    /// Committed messages per second
+    pub rate: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct PartitionStats {
+    pub partition: i32,
+    /// High watermark
+    pub offset: Option<i64>,
+    /// Difference of first and last high watermark
+    pub seen: i64,
+    pub rate: Option<f64>,
+    pub leader: Option<i32>,
+    pub isr: Vec<i32>,
+}
+
+#[derive(Debug)]
+pub struct BrokerStats {
+    pub id: i32,
+    pub host: Option<String>,
+    /// Number of partitions of scraped topics this broker leads
+    pub leaders: usize,
+    pub last_ok: Option<Instant>,
+    /// Error that made the broker be considered down
+    pub down: Option<String>,
+}
+
+impl TopicStats {
+    /// The group that is furthest behind, i.e. the one that's interesting
+    pub fn laggiest_group(&self) -> Option<&GroupStats> {
+        self.groups.iter().max_by_key(|g| g.lag)
+    }
 }
 
 impl Stats {
     pub fn ingesting(
         offrx: Receiver<scrape::Message>,
         scrape_interval: Duration,
+        retention: Duration,
+        clock: Clock,
     ) -> Result<Self, anyhow::Error> {
         Ok(Self {
+            retention,
+            last_discard: clock.now(),
+            clock,
             offrx,
             data: HashMap::new(),
+            metadata: HashMap::new(),
+            brokers: BTreeMap::new(),
             scrape_interval,
             metadata_error: None,
+            rounds: 0,
+            metadata_failures: 0,
+            broker_failures: 0,
+            partition_errors: HashMap::new(),
+            partition_failures: 0,
+            sample_error: None,
+            sample_failures: 0,
+            finished: None,
+            grouping: Grouping::default(),
+            grouped: HashMap::new(),
+            aggregates: HashSet::new(),
+            rules: Vec::new(),
+            alerts: BTreeMap::new(),
+            alert_tx: None,
+            loading_history: false,
+            forgotten: HashMap::new(),
         })
     }
+
+    /// Combine topics into aggregates
+    pub fn group_by(&mut self, grouping: Grouping) {
+        self.grouping = grouping;
+        self.grouped.clear();
+        self.aggregates.clear();
+    }
+
+    /// Check these rules whenever a topic finished a scrape round
+    pub fn alert_on(&mut self, rules: Vec<StallRule>) {
+        self.rules = rules.into_iter().map(Arc::new).collect();
+        self.alerts.clear();
+    }
+
     pub fn ingest(&mut self) -> Result<bool> {
+        let now = self.clock.now();
+        if now.saturating_duration_since(self.last_discard) > Duration::from_secs(1) {
+            self.last_discard = now;
+            self.discard_before(now.checked_sub(self.retention));
+        }
         let mut update_display = false;
         loop {
             match self.offrx.try_recv() {
@@ -55,31 +239,52 @@ impl Stats {
                     topic,
                     partition,
                     offset,
+                    low,
                     now,
                 }) => {
-                    let mut topdata = self
+                    self.partition_errors.remove(&(topic.clone(), partition));
+                    let topdata = self
                         .data
                         .entry(topic)
                         .or_insert_with(|| Vec::with_capacity(1))
                         .back_or_push();
                     let partdata = topdata.partitions.entry(partition);
                     let decreased = match &partdata {
-                        Entry::Occupied(partdata) => match partdata.get().values().rev().next() {
-                            Some(latest) => *latest > offset,
+                        Entry::Occupied(partdata) => match partdata.get().last() {
+                            Some(latest) => latest > offset,
                             None => false,
                         },
                         Entry::Vacant(_) => false,
                     };
                     if !decreased {
-                        partdata.or_insert_with(BTreeMap::new).insert(now, offset);
+                        partdata.or_default().insert(now, offset);
+                        topdata.lows.entry(partition).or_default().insert(now, low);
                     } else {
                         topdata.decreased += 1;
                     }
                     topdata.scraped += 1;
                 }
-                Ok(scrape::Message::RoundFinished { now, topic }) => {
-                    let topdatas = self.data.entry(topic).or_insert_with(Vec::default);
+                Ok(scrape::Message::RoundFinished {
+                    now,
+                    topic,
+                    skipped,
+                }) => {
+                    // Stored rounds are long over, and the stats at hand would only be those of the last one
+                    if let (Some(finished), false) = (self.finished.as_mut(), self.loading_history)
+                    {
+                        finished.insert(topic.clone(), now);
+                    }
+                    if let Entry::Vacant(entry) = self.grouped.entry(topic.clone()) {
+                        let aggregate = self.grouping.aggregate_of(&topic);
+                        self.aggregates.extend(aggregate.clone());
+                        entry.insert(aggregate);
+                    }
+                    let topdatas = self.data.entry(topic.clone()).or_default();
                     let topdata = topdatas.back_or_push();
+                    if topdata.skipped != skipped {
+                        topdata.skipped = skipped;
+                        update_display = true;
+                    }
                     if topdata.scraped > 0 {
                         if topdata.decreased <= topdata.partitions.len() / 2 {
                             topdata.scraped_interval.get_or_insert((now, now)).1 = now;
@@ -92,12 +297,129 @@ impl Stats {
                             topdatas.push(TopicData::default())
                         }
                     }
+                    if !self.loading_history {
+                        update_display |= self.evaluate_rules(&topic, now);
+                    }
+                }
+                Ok(scrape::Message::TopicMetadata { topic, partitions }) => {
+                    self.metadata.insert(topic, partitions);
+                }
+                Ok(scrape::Message::ScrapeFinished { .. }) => {
+                    self.rounds += 1;
+                }
+                Ok(scrape::Message::GroupOffsets {
+                    now,
+                    group,
+                    topic,
+                    partition,
+                    offset,
+                }) => {
+                    let polls = self
+                        .data
+                        .entry(topic)
+                        .or_insert_with(|| Vec::with_capacity(1))
+                        .back_or_push()
+                        .groups
+                        .entry(group)
+                        .or_default()
+                        .entry(partition)
+                        .or_default();
+                    if polls.last().map_or(false, |latest| latest > offset) {
+                        // Offsets were reset, rates across that are meaningless
+                        polls.clear();
+                    }
+                    polls.insert(now, offset);
+                }
+                Ok(scrape::Message::GroupRoundFinished { .. }) => {
+                    update_display = true;
+                }
+                Ok(scrape::Message::SampledSizes {
+                    topic,
+                    partition,
+                    messages,
+                    bytes,
+                    ..
+                }) => {
+                    self.sample_error = None;
+                    self.data
+                        .entry(topic)
+                        .or_insert_with(|| Vec::with_capacity(1))
+                        .back_or_push()
+                        .sizes
+                        .insert(partition, (messages, bytes));
+                }
+                Ok(scrape::Message::NewestMessage {
+                    topic,
+                    partition,
+                    timestamp,
+                    ..
+                }) => {
+                    self.sample_error = None;
+                    self.data
+                        .entry(topic)
+                        .or_insert_with(|| Vec::with_capacity(1))
+                        .back_or_push()
+                        .newest
+                        .insert(partition, timestamp);
+                    update_display = true;
                 }
                 Ok(scrape::Message::MetadataQueryFail(err)) => {
                     self.metadata_error = Some(err);
+                    self.metadata_failures += 1;
                     update_display = true;
                 }
-                Ok(msg) => anyhow::bail!("TODO: handle {:?}", msg),
+                Ok(scrape::Message::SampleQueryFail(err)) => {
+                    self.sample_error = Some(err);
+                    self.sample_failures += 1;
+                    update_display = true;
+                }
+                Ok(scrape::Message::Brokers(brokers)) => {
+                    let known = brokers.iter().map(|b| b.id).collect::<HashSet<_>>();
+                    self.brokers
+                        .retain(|id, state| known.contains(id) || state.failed.is_some());
+                    for scrape::BrokerInfo { id, host } in brokers {
+                        self.brokers.entry(id).or_default().host = Some(host);
+                    }
+                }
+                Ok(scrape::Message::BrokerQueryOk { now, broker }) => {
+                    let state = self.brokers.entry(broker).or_default();
+                    state.last_ok = Some(now);
+                    if state.failed.take().is_some() {
+                        update_display = true;
+                    }
+                }
+                Ok(scrape::Message::BrokerQueryFail { now, broker, error }) => {
+                    self.broker_failures += 1;
+                    self.brokers.entry(broker).or_default().failed = Some((now, error));
+                    update_display = true;
+                }
+                Ok(scrape::Message::PartitionQueryFail {
+                    topic,
+                    partition,
+                    error,
+                    ..
+                }) => {
+                    self.partition_failures += 1;
+                    self.partition_errors.insert((topic, partition), error);
+                    update_display = true;
+                }
+                Ok(scrape::Message::Reset) => {
+                    self.data.clear();
+                    self.forgotten.clear();
+                    self.metadata.clear();
+                    self.brokers.clear();
+                    self.partition_errors.clear();
+                    self.sample_error = None;
+                    self.alerts.clear();
+                    self.metadata_error = None;
+                    self.last_discard = self.clock.now();
+                    if let Some(finished) = self.finished.as_mut() {
+                        finished.clear();
+                    }
+                    update_display = true;
+                }
+                Ok(scrape::Message::LoadingHistory) => self.loading_history = true,
+                Ok(scrape::Message::HistoryLoaded) => self.loading_history = false,
                 Err(mpsc::TryRecvError::Empty) => return Ok(update_display),
                 Err(mpsc::TryRecvError::Disconnected) => {
                     // TODO: poll thread exit for an error for a second or so
@@ -108,129 +430,656 @@ impl Stats {
     }
     pub fn basestats(&self) -> impl '_ + Iterator<Item = TopicStats> {
         self.data.iter().flat_map(|(topic, padata)| {
-            padata.iter().rev().enumerate().map(|(idx, padata)| {
-                let mut seen = 0;
-                let mut total = 0;
-                let mut rate = None;
+            padata
+                .iter()
+                .rev()
+                .enumerate()
+                .map(move |(idx, padata)| topic_stats(topic, idx, padata))
+        })
+    }
+
+    /// Stats of the current generation of a topic, once it has finished a round
+    pub fn current_stats(&self, topic: &TopicName) -> Option<TopicStats> {
+        let padata = self.data.get(topic)?.last()?;
+        padata.scraped_interval?;
+        Some(topic_stats(topic, 0, padata))
+    }
+
+    /// Number of times a topic's offsets were reset, counted since we started watching
+    pub fn resets(&self, topic: &TopicName) -> usize {
+        let forgotten = self.forgotten.get(topic).copied().unwrap_or_default();
+        self.data
+            .get(topic)
+            .map_or(0, |padatas| padatas.len().saturating_sub(1))
+            + forgotten
+    }
+
+    /// Latest high watermark of each partition in the current generation of each topic
+    pub fn partition_offsets(&self) -> impl '_ + Iterator<Item = (&TopicName, i32, i64)> {
+        self.data.iter().flat_map(|(topic, padatas)| {
+            padatas.last().into_iter().flat_map(move |padata| {
                 padata
                     .partitions
-                    .values()
-                    .map(|polls| {
-                        let first = polls.values().next()?;
-                        let mut fromback = polls.iter().rev();
-                        let (end, last) = fromback.next()?;
-                        seen += last - first;
-                        total += last;
-                        let (pe, pl) = fromback.next()?;
-                        *rate.get_or_insert(0.) +=
-                            (last - pl) as f64 / end.duration_since(*pe).as_secs_f64();
-                        Some(())
-                    })
-                    .for_each(|_| ());
-                TopicStats {
-                    topic: Topic {
-                        name: topic.to_owned(),
-                        stat_idx: idx,
-                    },
-                    total,
-                    seen,
+                    .iter()
+                    .filter_map(move |(partition, polls)| Some((topic, *partition, polls.last()?)))
+            })
+        })
+    }
+
+    pub fn broker_stats(&self) -> Vec<BrokerStats> {
+        let mut leaders = HashMap::<i32, usize>::new();
+        for partition in self.metadata.values().flatten() {
+            *leaders.entry(partition.leader).or_default() += 1;
+        }
+        self.brokers
+            .iter()
+            .map(|(&id, state)| BrokerStats {
+                id,
+                host: state.host.clone(),
+                leaders: leaders.get(&id).copied().unwrap_or_default(),
+                last_ok: state.last_ok,
+                down: state.failed.as_ref().map(|(_, err)| err.clone()),
+            })
+            .collect()
+    }
+
+    /// Send alerts that start or stop firing to `tx`
+    pub fn notify_alerts(&mut self, tx: mpsc::Sender<AlertEvent>) {
+        self.alert_tx = Some(tx);
+    }
+
+    /// Currently firing alerts, by topic
+    pub fn alerts(&self) -> impl Iterator<Item = &Alert> {
+        self.alerts.values()
+    }
+
+    /// Whether an alert is firing for a topic, or for any member of an aggregate
+    pub fn is_alerting(&self, topic: &Topic) -> bool {
+        topic.stat_idx == 0
+            && self.alerts.keys().any(|(name, _)| {
+                *name == topic.name
+                    || self.grouped.get(name).and_then(Option::as_ref) == Some(&topic.name)
+            })
+    }
+
+    /// Update the alerts of a topic that just finished a round. Tells whether any started or stopped firing.
+    fn evaluate_rules(&mut self, topic: &TopicName, now: Instant) -> bool {
+        let padata = match self.data.get(topic).and_then(|padatas| padatas.last()) {
+            Some(padata) => padata,
+            None => return false,
+        };
+        let (_, _, rate) = sums(padata.partitions.values());
+        let mut changed = false;
+        for (idx, rule) in self.rules.iter().enumerate() {
+            if !rule.applies_to(topic) {
+                continue;
+            }
+            let stalled = match stalled(rule.condition, padata, rate, now) {
+                Some(stalled) => stalled,
+                None => continue, // Not enough history yet
+            };
+            let key = (topic.clone(), idx);
+            let event = match (stalled, self.alerts.entry(key)) {
+                (true, btree_map::Entry::Occupied(mut alert)) => {
+                    alert.get_mut().rate = rate;
+                    None
+                }
+                (true, btree_map::Entry::Vacant(entry)) => {
+                    let alert = entry.insert(Alert {
+                        topic: topic.clone(),
+                        rule: rule.clone(),
+                        rate,
+                        since: now,
+                    });
+                    Some(AlertEvent::Firing(alert.clone()))
+                }
+                (false, btree_map::Entry::Occupied(alert)) => Some(AlertEvent::Resolved(Alert {
                     rate,
+                    ..alert.remove()
+                })),
+                (false, btree_map::Entry::Vacant(_)) => None,
+            };
+            if let Some(event) = event {
+                changed = true;
+                if let Some(tx) = self.alert_tx.as_ref() {
+                    // Nobody listening anymore is no reason to stop
+                    tx.send(event).ok();
+                }
+            }
+        }
+        changed
+    }
+
+    /// How long ago the newest message of a topic was produced, according to its timestamp
+    pub fn message_age(&self, stats: &TopicStats) -> Option<Duration> {
+        let age = self.clock.wall_now().timestamp_millis() - stats.newest_message?;
+        Some(Duration::from_millis(cmp::max(age, 0) as u64))
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Start remembering which topics finished a scrape round
+    pub fn track_finished(&mut self) {
+        self.finished.get_or_insert_with(HashMap::new);
+    }
+
+    /// Each topic once, with its last round, as only the [current stats](Self::current_stats) are at hand.
+    /// Oldest first.
+    pub fn take_finished(&mut self) -> Vec<(TopicName, Instant)> {
+        let mut finished = self
+            .finished
+            .as_mut()
+            .map(mem::take)
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<Vec<_>>();
+        finished.sort_by_key(|&(_, at)| at);
+        finished
+    }
+
+    /// Current generations first, then by activity
+    pub fn sorted_basestats(&self) -> Vec<TopicStats> {
+        let mut basestats = self.basestats().collect::<Vec<_>>();
+        sort_by_activity(&mut basestats);
+        basestats
+    }
+
+    /// Stats of all aggregates, members sorted like [sorted_basestats](Self::sorted_basestats)
+    pub fn aggregate_stats(&self) -> Vec<AggregateStats> {
+        let mut members = BTreeMap::<&TopicName, Vec<TopicStats>>::new();
+        for (topic, aggregate) in &self.grouped {
+            if let (Some(aggregate), Some(stats)) = (aggregate, self.current_stats(topic)) {
+                members.entry(aggregate).or_default().push(stats);
+            }
+        }
+        members
+            .into_iter()
+            .map(|(aggregate, mut members)| {
+                sort_by_activity(&mut members);
+                AggregateStats {
+                    stats: aggregate_stats(aggregate, &members),
+                    members,
                 }
             })
-        })
+            .collect()
+    }
+
+    pub fn is_aggregate(&self, topic: &Topic) -> bool {
+        self.aggregates.contains(&topic.name)
+    }
+
+    /// The aggregate a topic's current generation belongs to
+    pub fn aggregate_of(&self, topic: &Topic) -> Option<&TopicName> {
+        match topic.stat_idx {
+            0 => self.grouped.get(&topic.name)?.as_ref(),
+            _ => None,
+        }
     }
 
+    /// Rate of each bucket over the last `span` before `now`, as (seconds relative to now, rate)
     pub fn rates(
         &self,
         topic: &Topic,
-        now: Instant,
+        (now, span): (Instant, Duration),
         bucket_size: Duration,
+        unit: Unit,
     ) -> Option<Vec<(f64, f64)>> {
-        let mut maxv = 1.0f64;
-        let TopicData {
-            partitions: padata,
-            scraped_interval,
-            ..
-        } = self
-            .data
-            .get(&topic.name)?
-            .iter()
-            .rev()
-            .nth(topic.stat_idx)?;
-        let (scrape_start, scrape_end) = scraped_interval.clone()?;
-        if scrape_start > now {
-            // Just avoid some WTFery
-            return None;
-        }
-        let bucket_size_f = bucket_size.as_secs_f64();
-        let mut buckets = (0..((scrape_end - scrape_start).as_secs_f64() / bucket_size_f) as usize)
-            .map(|idx| {
-                (
-                    bucket_size_f * idx as f64
-                        - ((now - scrape_start) + bucket_size / 2).as_secs_f64(),
-                    0f64,
-                )
+        let topdatas = self.topic_datas(topic);
+        bucketed_rates(
+            weighted(&topdatas, unit)?
+                .into_iter()
+                .flat_map(|(weight, topdata)| {
+                    topdata
+                        .partitions
+                        .values()
+                        .map(move |series| (weight, series.view(bucket_size)))
+                }),
+            scraped_interval(&topdatas)?,
+            (now, span),
+            bucket_size,
+        )
+    }
+
+    /// Like [rates](Self::rates), but summed over the current generation of all topics
+    pub fn total_rates(
+        &self,
+        (now, span): (Instant, Duration),
+        bucket_size: Duration,
+        unit: Unit,
+    ) -> Option<Vec<(f64, f64)>> {
+        let current = self.current_datas();
+        bucketed_rates(
+            weighted(&current, unit)?
+                .into_iter()
+                .flat_map(|(weight, topdata)| {
+                    topdata
+                        .partitions
+                        .values()
+                        .map(move |series| (weight, series.view(bucket_size)))
+                }),
+            scraped_interval(&current)?,
+            (now, span),
+            bucket_size,
+        )
+    }
+
+    pub fn cluster_stats(&self) -> ClusterStats {
+        let current = self.current_datas();
+        ClusterStats {
+            rate: current
+                .iter()
+                .filter_map(|topdata| sums(topdata.partitions.values()).2)
+                .reduce(|a, b| a + b),
+            byte_rate: current
+                .iter()
+                .filter_map(|topdata| {
+                    Some(sums(topdata.partitions.values()).2? * topdata.message_size(None)?)
+                })
+                .reduce(|a, b| a + b),
+            topics: current.len(),
+            partitions: current.iter().map(|topdata| topdata.partitions.len()).sum(),
+            brokers: self.brokers.len(),
+            last_scrape: scraped_interval(&current).map(|(_, end)| end),
+        }
+    }
+
+    /// Like [rates](Self::rates), but for the offsets committed by a consumer group
+    pub fn consume_rates(
+        &self,
+        topic: &Topic,
+        group: &str,
+        (now, span): (Instant, Duration),
+        bucket_size: Duration,
+        unit: Unit,
+    ) -> Option<Vec<(f64, f64)>> {
+        let topdatas = self.topic_datas(topic);
+        bucketed_rates(
+            weighted(&topdatas, unit)?
+                .into_iter()
+                .filter_map(|(weight, topdata)| Some((weight, topdata.groups.get(group)?)))
+                .flat_map(|(weight, committed)| {
+                    committed
+                        .values()
+                        .map(move |series| (weight, series.view(bucket_size)))
+                }),
+            scraped_interval(&topdatas)?,
+            (now, span),
+            bucket_size,
+        )
+    }
+
+    /// Like [rates](Self::rates), but for a single partition
+    pub fn partition_rates(
+        &self,
+        topic: &Topic,
+        partition: i32,
+        (now, span): (Instant, Duration),
+        bucket_size: Duration,
+        unit: Unit,
+    ) -> Option<Vec<(f64, f64)>> {
+        let topdata = self.topic_data(topic)?;
+        let weight = match unit {
+            Unit::Messages => 1.,
+            Unit::Bytes => topdata.message_size(Some(partition))?,
+        };
+        bucketed_rates(
+            topdata
+                .partitions
+                .get(&partition)
+                .map(|series| (weight, series.view(bucket_size))),
+            topdata.scraped_interval?,
+            (now, span),
+            bucket_size,
+        )
+    }
+
+    /// Offsets and rates of each partition, along with the latest known leader and ISR
+    pub fn partition_stats(&self, topic: &Topic) -> Vec<PartitionStats> {
+        let polls = self.topic_data(topic).map(|topdata| &topdata.partitions);
+        let metadata = match topic.stat_idx {
+            0 => self.metadata.get(&topic.name),
+            _ => None, // Old generation, whatever is known is likely wrong
+        };
+        let partitions = polls
+            .into_iter()
+            .flat_map(HashMap::keys)
+            .copied()
+            .chain(metadata.into_iter().flatten().map(|p| p.id))
+            .collect::<BTreeSet<_>>();
+        partitions
+            .into_iter()
+            .map(|partition| {
+                let polls = polls.and_then(|polls| polls.get(&partition));
+                let (total, seen, rate) = sums(polls);
+                let info = metadata.into_iter().flatten().find(|p| p.id == partition);
+                PartitionStats {
+                    partition,
+                    offset: polls.map(|_| total),
+                    seen,
+                    rate,
+                    leader: info.map(|p| p.leader),
+                    isr: info.map(|p| p.isr.clone()).unwrap_or_default(),
+                }
             })
-            .collect::<Vec<_>>();
-        // TODO: tests... :(
-        for (_, polls) in padata.iter() {
-            for ((ai, ao), (bi, bo)) in polls.iter().tuple_windows() {
-                let diff = bo - ao;
-                let aedge = ai.checked_duration_since(scrape_start);
-                let bedge = bi.checked_duration_since(scrape_start);
-                let aidx = aedge.map(|aedge| (aedge.as_secs_f64() / bucket_size_f) as usize);
-                let bidx = bedge.map(|bedge| (bedge.as_secs_f64() / bucket_size_f) as usize);
-                let dur = *bi - *ai;
-                if aidx == bidx {
-                    if let Some((_, v)) = bidx.and_then(|bidx| buckets.get_mut(bidx)) {
-                        *v += diff as f64 / bucket_size_f;
-                        maxv = maxv.max(*v);
-                    }
-                } else {
-                    let rate = diff as f64 / dur.as_secs_f64();
-                    if let Some((_, v)) = aidx.and_then(|aidx| buckets.get_mut(aidx)) {
-                        *v += rate
-                            * ((aidx.unwrap() + 1) as f64
-                                - aedge.unwrap().as_secs_f64() / bucket_size_f);
-                        maxv = maxv.max(*v);
-                    }
-                    let aidx0 = aidx.map(|aidx| aidx + 1).unwrap_or(0);
-                    let bidxm = bidx
-                        .map(|bidx| bidx.saturating_sub(aidx0))
-                        .unwrap_or(usize::MAX);
-                    for (_, v) in buckets.iter_mut().skip(aidx0).take(bidxm) {
-                        *v += rate;
-                        maxv = maxv.max(*v);
-                    }
-                    if let Some((_, v)) = bidx.and_then(|bidx| buckets.get_mut(bidx)) {
-                        *v += rate
-                            * (bedge.unwrap().as_secs_f64() / bucket_size_f - bidx.unwrap() as f64);
-                        maxv = maxv.max(*v);
-                    }
+            .collect()
+    }
+
+    fn topic_data(&self, topic: &Topic) -> Option<&TopicData> {
+        self.data.get(&topic.name)?.iter().rev().nth(topic.stat_idx)
+    }
+
+    /// Current generation of all topics that have finished a round
+    fn current_datas(&self) -> Vec<&TopicData> {
+        self.data
+            .values()
+            .filter_map(|padatas| padatas.last())
+            .filter(|padata| padata.scraped_interval.is_some())
+            .collect()
+    }
+
+    /// The topic's data, or the current data of all its members if it's an aggregate
+    fn topic_datas(&self, topic: &Topic) -> Vec<&TopicData> {
+        match self.is_aggregate(topic) {
+            true => self
+                .grouped
+                .iter()
+                .filter(|(_, aggregate)| aggregate.as_ref() == Some(&topic.name))
+                .filter_map(|(member, _)| self.data.get(member)?.last())
+                .collect(),
+            false => self.topic_data(topic).into_iter().collect(),
+        }
+    }
+}
+
+/// Current generations first, then by messages seen
+pub fn sort_by_activity(basestats: &mut [TopicStats]) {
+    basestats.sort_by_key(|s| (s.topic.stat_idx, cmp::Reverse((s.seen, s.total))));
+}
+
+/// Whether a topic is stalled, if there's enough history to tell
+fn stalled(
+    condition: Condition,
+    padata: &TopicData,
+    rate: Option<f64>,
+    now: Instant,
+) -> Option<bool> {
+    match condition {
+        Condition::Slow { max_rate, window } => {
+            Some(average_rate(padata, now.checked_sub(window)?)? <= max_rate)
+        }
+        Condition::Drop { fraction, window } => {
+            let average = average_rate(padata, now.checked_sub(window)?)?;
+            Some(average > 0. && rate? < fraction * average)
+        }
+    }
+}
+
+/// Rate between the latest offsets and those at `since`, if all partitions go back that far
+fn average_rate(padata: &TopicData, since: Instant) -> Option<f64> {
+    if padata.partitions.is_empty() {
+        return None;
+    }
+    let mut rate = 0.;
+    for series in padata.partitions.values() {
+        let (start, first) = series.at(since)?;
+        let (&end, &last) = series.recent().iter().next_back()?;
+        if end > start {
+            rate += (last - first) as f64 / (end - start).as_secs_f64();
+        }
+    }
+    Some(rate)
+}
+
+/// Topic datas along with what to multiply their message counts with to get the unit.
+/// Without any sizes sampled, there is nothing to show in bytes.
+fn weighted<'a>(topdatas: &[&'a TopicData], unit: Unit) -> Option<Vec<(f64, &'a TopicData)>> {
+    let weighted = topdatas
+        .iter()
+        .filter_map(|&topdata| match unit {
+            Unit::Messages => Some((1., topdata)),
+            Unit::Bytes => Some((topdata.message_size(None)?, topdata)),
+        })
+        .collect::<Vec<_>>();
+    match weighted.is_empty() {
+        true => None,
+        false => Some(weighted),
+    }
+}
+
+impl TopicData {
+    /// Average size of the sampled messages of a partition, or of the whole topic
+    fn message_size(&self, partition: Option<i32>) -> Option<f64> {
+        let (messages, bytes) = match partition.and_then(|p| self.sizes.get(&p)) {
+            Some(&sampled) => sampled,
+            None => self
+                .sizes
+                .values()
+                .fold((0, 0), |(m, b), &(messages, bytes)| {
+                    (m + messages, b + bytes)
+                }),
+        };
+        match messages {
+            0 => None,
+            _ => Some(bytes as f64 / messages as f64),
+        }
+    }
+}
+
+/// Earliest start and latest end of scraping
+fn scraped_interval(topdatas: &[&TopicData]) -> Option<(Instant, Instant)> {
+    topdatas
+        .iter()
+        .filter_map(|topdata| topdata.scraped_interval)
+        .reduce(|(s1, e1), (s2, e2)| (cmp::min(s1, s2), cmp::max(e1, e2)))
+}
+
+fn aggregate_stats(aggregate: &TopicName, members: &[TopicStats]) -> TopicStats {
+    let add = |a: Option<f64>, b: Option<f64>| match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    };
+    let mut groups = BTreeMap::<&str, GroupStats>::new();
+    for group in members.iter().flat_map(|s| &s.groups) {
+        let sum = groups.entry(&group.group).or_insert_with(|| GroupStats {
+            group: group.group.clone(),
+            lag: 0,
+            rate: None,
+        });
+        sum.lag += group.lag;
+        sum.rate = add(sum.rate, group.rate);
+    }
+    let rate = members.iter().map(|s| s.rate).fold(None, add);
+    let byte_rate = members.iter().map(|s| s.byte_rate).fold(None, add);
+    TopicStats {
+        topic: Topic {
+            name: aggregate.clone(),
+            stat_idx: 0,
+        },
+        total: members.iter().map(|s| s.total).sum(),
+        seen: members.iter().map(|s| s.seen).sum(),
+        rate,
+        retained: members.iter().map(|s| s.retained).sum(),
+        deletion_rate: members.iter().map(|s| s.deletion_rate).fold(None, add),
+        groups: groups.into_values().collect(),
+        skipped: members.iter().map(|s| s.skipped).sum(),
+        message_size: match (byte_rate, rate) {
+            (Some(byte_rate), Some(rate)) if rate > 0. => Some(byte_rate / rate),
+            _ => None,
+        },
+        byte_rate,
+        newest_message: members.iter().filter_map(|s| s.newest_message).max(),
+    }
+}
+
+fn topic_stats(topic: &TopicName, idx: usize, padata: &TopicData) -> TopicStats {
+    let (total, seen, rate) = sums(padata.partitions.values());
+    let (low_total, _, deletion_rate) = sums(padata.lows.values());
+    let groups = padata
+        .groups
+        .iter()
+        .map(|(group, committed)| {
+            let (_, _, rate) = sums(committed.values());
+            let lag = committed
+                .iter()
+                .filter_map(|(partition, committed)| {
+                    let high = padata.partitions.get(partition)?.last()?;
+                    let committed = committed.last()?;
+                    Some(cmp::max(high - committed, 0))
+                })
+                .sum();
+            GroupStats {
+                group: group.to_owned(),
+                lag,
+                rate,
+            }
+        })
+        .collect();
+    TopicStats {
+        topic: Topic {
+            name: topic.clone(),
+            stat_idx: idx,
+        },
+        total,
+        seen,
+        rate,
+        retained: total - low_total,
+        deletion_rate,
+        groups,
+        skipped: padata.skipped,
+        message_size: padata.message_size(None),
+        byte_rate: rate.zip(padata.message_size(None)).map(|(r, s)| r * s),
+        newest_message: padata.newest.values().copied().max(),
+    }
+}
+
+/// Sum of latest offsets, sum of differences between first and latest offsets, and rate
+fn sums<'a>(padata: impl IntoIterator<Item = &'a Series>) -> (i64, i64, Option<f64>) {
+    let mut seen = 0;
+    let mut total = 0;
+    let mut rate = None;
+    padata
+        .into_iter()
+        .map(|polls| {
+            let (_, first) = polls.first()?;
+            let mut fromback = polls.recent().iter().rev();
+            let (end, last) = fromback.next()?;
+            seen += last - first;
+            total += last;
+            let (pe, pl) = fromback.next()?;
+            *rate.get_or_insert(0.) += (last - pl) as f64 / end.duration_since(*pe).as_secs_f64();
+            Some(())
+        })
+        .for_each(|_| ());
+    (total, seen, rate)
+}
+
+/// Rates of offset series, each multiplied by a weight, summed up per bucket
+fn bucketed_rates<'a>(
+    padata: impl IntoIterator<Item = (f64, impl IntoIterator<Item = (&'a Instant, &'a i64)>)>,
+    (scrape_start, scrape_end): (Instant, Instant),
+    (now, span): (Instant, Duration),
+    bucket_size: Duration,
+) -> Option<Vec<(f64, f64)>> {
+    if scrape_start > now {
+        // Just avoid some WTFery
+        return None;
+    }
+    // Only bucket what gets drawn, however long we've been scraping
+    let scrape_start = match now.checked_duration_since(scrape_start) {
+        Some(scraping) if scraping > span => now - span,
+        _ => scrape_start,
+    };
+    let mut maxv = 1.0f64;
+    let bucket_size_f = bucket_size.as_secs_f64();
+    let scraped = scrape_end.saturating_duration_since(scrape_start);
+    let mut buckets = (0..(scraped.as_secs_f64() / bucket_size_f) as usize)
+        .map(|idx| {
+            (
+                bucket_size_f * idx as f64 - ((now - scrape_start) + bucket_size / 2).as_secs_f64(),
+                0f64,
+            )
+        })
+        .collect::<Vec<_>>();
+    for (weight, polls) in padata {
+        let polls = polls
+            .into_iter()
+            .tuple_windows()
+            .skip_while(|(_, (&bi, _))| bi < scrape_start);
+        for ((ai, ao), (bi, bo)) in polls {
+            let diff = (bo - ao) as f64 * weight;
+            let aedge = ai.checked_duration_since(scrape_start);
+            let bedge = bi.checked_duration_since(scrape_start);
+            let aidx = aedge.map(|aedge| (aedge.as_secs_f64() / bucket_size_f) as usize);
+            let bidx = bedge.map(|bedge| (bedge.as_secs_f64() / bucket_size_f) as usize);
+            let dur = *bi - *ai;
+            if aidx == bidx {
+                if let Some((_, v)) = bidx.and_then(|bidx| buckets.get_mut(bidx)) {
+                    *v += diff / bucket_size_f;
+                    maxv = maxv.max(*v);
+                }
+            } else {
+                let rate = diff / dur.as_secs_f64();
+                if let Some((_, v)) = aidx.and_then(|aidx| buckets.get_mut(aidx)) {
+                    *v += rate
+                        * ((aidx.unwrap() + 1) as f64
+                            - aedge.unwrap().as_secs_f64() / bucket_size_f);
+                    maxv = maxv.max(*v);
+                }
+                let aidx0 = aidx.map(|aidx| aidx + 1).unwrap_or(0);
+                let bidxm = bidx
+                    .map(|bidx| bidx.saturating_sub(aidx0))
+                    .unwrap_or(usize::MAX);
+                for (_, v) in buckets.iter_mut().skip(aidx0).take(bidxm) {
+                    *v += rate;
+                    maxv = maxv.max(*v);
+                }
+                if let Some((_, v)) = bidx.and_then(|bidx| buckets.get_mut(bidx)) {
+                    *v += rate
+                        * (bedge.unwrap().as_secs_f64() / bucket_size_f - bidx.unwrap() as f64);
+                    maxv = maxv.max(*v);
                 }
             }
         }
-        Some(buckets)
     }
+    Some(buckets)
+}
 
-    pub fn discard_before(&mut self, discard: Instant) {
-        let discard = match discard.checked_sub(self.scrape_interval) {
-            Some(discard) => discard,
-            None => return,
-        };
-        for padatas in self.data.values_mut() {
-            for TopicData { partitions, .. } in padatas {
-                for polls in partitions.values_mut() {
-                    while let Some(first) = polls
-                        .keys()
-                        .next()
-                        .map(Clone::clone)
-                        .filter(|t| t < &discard)
-                    {
-                        polls.remove(&first);
-                    }
+impl Stats {
+    /// Forget offsets from before `discard`, and thin out the older ones in any case
+    pub fn discard_before(&mut self, discard: Option<Instant>) {
+        let discard = discard.and_then(|discard| discard.checked_sub(self.scrape_interval));
+        for (topic, padatas) in self.data.iter_mut() {
+            for TopicData {
+                partitions,
+                lows,
+                groups,
+                scraped_interval,
+                ..
+            } in padatas.iter_mut()
+            {
+                for polls in partitions
+                    .values_mut()
+                    .chain(lows.values_mut())
+                    .chain(groups.values_mut().flat_map(HashMap::values_mut))
+                {
+                    polls.discard_before(discard);
+                }
+                if let (Some((start, end)), Some(discard)) = (scraped_interval.as_mut(), discard) {
+                    *start = cmp::min(cmp::max(*start, discard), *end);
                 }
             }
+            // Old generations go once all their offsets are gone, the current one stays even if empty
+            let empty = padatas
+                .iter()
+                .take(padatas.len().saturating_sub(1))
+                .take_while(|padata| padata.partitions.values().all(|s| s.first().is_none()))
+                .count();
+            if empty > 0 {
+                padatas.drain(..empty);
+                *self.forgotten.entry(topic.clone()).or_default() += empty;
+            }
         }
     }
 }
@@ -244,9 +1093,610 @@ impl<T: Default> BackOrPush for Vec<T> {
     type Element = T;
 
     fn back_or_push(&mut self) -> &mut Self::Element {
-        if self.len() == 0 {
+        if self.is_empty() {
             self.push(Default::default());
         }
-        self.iter_mut().rev().next().unwrap()
+        self.iter_mut().next_back().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::SimClock,
+        scrape::{
+            query_bad, query_freshness, query_groups, query_offsets, query_sizes, OffsetSource,
+            State,
+        },
+        synthetic::{Outage, Synthetic, SyntheticTopic},
+    };
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// Scrapes a synthetic cluster on a clock that only moves when told to
+    struct Harness {
+        clock: Arc<SimClock>,
+        source: Box<dyn OffsetSource>,
+        state: State,
+        tx: mpsc::SyncSender<scrape::Message>,
+        stats: Stats,
+    }
+
+    impl Harness {
+        fn new(topics: Vec<SyntheticTopic>, outages: Vec<Outage>) -> Self {
+            Self::wrapping(topics, outages, |source| Box::new(source))
+        }
+
+        /// With the synthetic cluster behind a source that misbehaves in some way
+        fn wrapping(
+            topics: Vec<SyntheticTopic>,
+            outages: Vec<Outage>,
+            wrap: impl FnOnce(Synthetic) -> Box<dyn OffsetSource>,
+        ) -> Self {
+            let clock = Arc::new(SimClock::new(Local::now()));
+            let (tx, rx) = mpsc::sync_channel(1_000_000);
+            Harness {
+                source: wrap(Synthetic::new(
+                    Clock::Sim(clock.clone()),
+                    3,
+                    topics,
+                    outages,
+                )),
+                state: State::new(&Opts::from_iter(["totop", "--demo"])),
+                stats: Stats::ingesting(
+                    rx,
+                    INTERVAL,
+                    Duration::from_secs(3600),
+                    Clock::Sim(clock.clone()),
+                )
+                .unwrap(),
+                clock,
+                tx,
+            }
+        }
+
+        /// Scrape, ingest, then let one scrape interval pass
+        fn round(&mut self) {
+            query_offsets(&self.state, &self.tx, &*self.source).unwrap();
+            query_bad(&self.state, &self.tx, &*self.source).unwrap();
+            query_groups(&self.state, &self.tx, &*self.source).unwrap();
+            query_sizes(&self.state, &self.tx, &*self.source).unwrap();
+            query_freshness(&self.state, &self.tx, &*self.source).unwrap();
+            self.stats.ingest().unwrap();
+            self.clock.advance(INTERVAL);
+        }
+
+        fn rounds(&mut self, n: usize) {
+            for _ in 0..n {
+                self.round();
+            }
+        }
+    }
+
+    fn topic(name: &str, partitions: i32, rate: f64) -> SyntheticTopic {
+        SyntheticTopic {
+            name: name.into(),
+            partitions,
+            rate,
+            ..SyntheticTopic::default()
+        }
+    }
+
+    fn current(name: &str) -> Topic {
+        Topic {
+            name: name.into(),
+            stat_idx: 0,
+        }
+    }
+
+    #[test]
+    fn steady_rate() {
+        let mut h = Harness::new(vec![topic("steady", 3, 60.)], vec![]);
+        h.rounds(7);
+        let stats = h.stats.current_stats(&"steady".into()).unwrap();
+        assert_eq!(stats.total, 3600);
+        assert_eq!(stats.seen, 3600);
+        assert!((stats.rate.unwrap() - 60.).abs() < 1e-9);
+        assert_eq!(stats.skipped, 0);
+        assert_eq!(h.stats.rounds, 7);
+
+        let rates = h
+            .stats
+            .rates(
+                &current("steady"),
+                (h.clock.now(), HOUR),
+                INTERVAL,
+                Unit::Messages,
+            )
+            .unwrap();
+        assert_eq!(rates.len(), 6);
+        for (_, rate) in rates {
+            assert!((rate - 60.).abs() < 1e-9, "{}", rate);
+        }
+    }
+
+    #[test]
+    fn cluster_totals() {
+        let mut h = Harness::new(vec![topic("a", 3, 60.), topic("b", 2, 30.)], vec![]);
+        h.rounds(4);
+        let cluster = h.stats.cluster_stats();
+        assert!((cluster.rate.unwrap() - 90.).abs() < 1e-9);
+        assert_eq!((cluster.topics, cluster.partitions), (2, 5));
+        assert_eq!(cluster.brokers, 3);
+        assert_eq!(cluster.last_scrape, Some(h.clock.now() - INTERVAL));
+
+        let rates = h
+            .stats
+            .total_rates((h.clock.now(), HOUR), INTERVAL, Unit::Messages)
+            .unwrap();
+        assert_eq!(rates.len(), 3);
+        for (_, rate) in rates {
+            assert!((rate - 90.).abs() < 1e-9, "{}", rate);
+        }
+    }
+
+    #[test]
+    fn byte_rates_from_sampled_sizes() {
+        let sized = |name, message_size| SyntheticTopic {
+            message_size,
+            ..topic(name, 2, 30.)
+        };
+        let mut h = Harness::new(vec![sized("small", 50), sized("large", 2000)], vec![]);
+        h.rounds(4);
+        let small = h.stats.current_stats(&"small".into()).unwrap();
+        assert_eq!(small.message_size, Some(50.));
+        assert!((small.byte_rate.unwrap() - 1500.).abs() < 1e-9);
+        let cluster = h.stats.cluster_stats();
+        assert!((cluster.byte_rate.unwrap() - 61500.).abs() < 1e-9);
+
+        let rates = h
+            .stats
+            .rates(
+                &current("large"),
+                (h.clock.now(), HOUR),
+                INTERVAL,
+                Unit::Bytes,
+            )
+            .unwrap();
+        for (_, rate) in rates {
+            assert!((rate - 60000.).abs() < 1e-9, "{}", rate);
+        }
+        let partition = h
+            .stats
+            .partition_rates(
+                &current("large"),
+                1,
+                (h.clock.now(), HOUR),
+                INTERVAL,
+                Unit::Bytes,
+            )
+            .unwrap();
+        assert!((partition[0].1 - 40000.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn newest_message_age() {
+        let backfill = SyntheticTopic {
+            delay: Duration::from_secs(7200),
+            ..topic("backfill", 2, 10.)
+        };
+        let mut h = Harness::new(
+            vec![topic("live", 2, 10.), backfill, topic("empty", 1, 0.)],
+            vec![],
+        );
+        h.rounds(3);
+        let age = |name: &str| {
+            let stats = h.stats.current_stats(&name.into()).unwrap();
+            h.stats.message_age(&stats)
+        };
+        // Read right after the offsets, then one scrape interval passed
+        assert_eq!(age("live"), Some(INTERVAL));
+        assert_eq!(age("backfill"), Some(INTERVAL + Duration::from_secs(7200)));
+        assert_eq!(age("empty"), None);
+    }
+
+    #[test]
+    fn stall_rules() {
+        let wavy = SyntheticTopic {
+            wave: Some((0.95, Duration::from_secs(600))),
+            ..topic("wavy", 1, 100.)
+        };
+        let mut h = Harness::new(
+            vec![topic("idle", 1, 0.), topic("steady", 1, 10.), wavy],
+            vec![],
+        );
+        h.stats.alert_on(vec![
+            "0 for 1m".parse().unwrap(),
+            "wavy: 10% of 10m".parse().unwrap(),
+        ]);
+        let (tx, rx) = mpsc::channel();
+        h.stats.notify_alerts(tx);
+        h.rounds(6);
+        assert_eq!(h.stats.alerts().count(), 0, "Not enough history yet");
+        h.rounds(1);
+        let firing = h
+            .stats
+            .alerts()
+            .map(|a| a.topic.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(firing, ["idle"]);
+        assert!(h.stats.is_alerting(&current("idle")));
+        assert!(!h.stats.is_alerting(&current("steady")));
+
+        // The wave bottoms out at 5% of the average after 17.5 min
+        h.rounds(125);
+        let events = rx
+            .try_iter()
+            .map(|event| match event {
+                AlertEvent::Firing(alert) => (true, alert.topic.to_string()),
+                AlertEvent::Resolved(alert) => (false, alert.topic.to_string()),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                (true, "idle".to_owned()),
+                (true, "wavy".to_owned()),
+                (false, "wavy".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_starts_new_generation() {
+        let mut h = Harness::new(
+            vec![SyntheticTopic {
+                reset_every: Some(Duration::from_secs(60)),
+                ..topic("flaky", 3, 6.)
+            }],
+            vec![],
+        );
+        h.rounds(6);
+        assert_eq!(h.stats.resets(&"flaky".into()), 0);
+        // Offsets go back to 0 at 60 s
+        h.rounds(4);
+        assert_eq!(h.stats.resets(&"flaky".into()), 1);
+        let generations = h.stats.sorted_basestats();
+        assert_eq!(generations.len(), 2);
+        let (new, old) = (&generations[0], &generations[1]);
+        assert_eq!(old.topic.stat_idx, 1);
+        assert_eq!(old.total, 300);
+        // The first scrape after the reset is lost
+        assert_eq!(new.topic.stat_idx, 0);
+        assert_eq!(new.total, 180);
+        assert_eq!(new.seen, 120);
+        assert!((new.rate.unwrap() - 6.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn outage_skips_partitions_until_recovered() {
+        let mut h = Harness::new(
+            vec![topic("spread", 3, 6.)],
+            vec![Outage {
+                broker: 2,
+                at: Duration::from_secs(20),
+                length: Duration::from_secs(20),
+                every: Duration::from_secs(3600),
+            }],
+        );
+        h.rounds(3);
+        let down = |h: &Harness| {
+            h.stats
+                .broker_stats()
+                .into_iter()
+                .filter(|b| b.down.is_some())
+                .map(|b| b.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(down(&h), [2]);
+        assert_eq!(h.stats.current_stats(&"spread".into()).unwrap().skipped, 1);
+        h.rounds(1);
+        assert_eq!(h.stats.current_stats(&"spread".into()).unwrap().skipped, 1);
+        // Recovery is noticed at 40 s, the next round queries all partitions again
+        h.rounds(2);
+        assert_eq!(down(&h), Vec::<i32>::new());
+        assert_eq!(h.stats.current_stats(&"spread".into()).unwrap().skipped, 0);
+        assert_eq!(h.stats.broker_failures, 1);
+        // Skipped partitions don't look like a reset
+        assert_eq!(h.stats.resets(&"spread".into()), 0);
+    }
+
+    /// Partition 0 has no leader, and partition 1 fails on its own. Optionally, nothing can be read.
+    struct Flaky(Synthetic, bool);
+
+    impl OffsetSource for Flaky {
+        fn metadata(&self, timeout: Duration) -> Result<scrape::Metadata> {
+            let mut metadata = self.0.metadata(timeout)?;
+            for topic in &mut metadata.topics {
+                topic.partitions[0].leader = -1;
+            }
+            Ok(metadata)
+        }
+
+        fn watermarks(
+            &self,
+            partitions: &[(&str, i32)],
+            timeout: Duration,
+        ) -> Result<(
+            Instant,
+            Vec<scrape::Watermarks>,
+            Vec<scrape::PartitionError>,
+        )> {
+            let (now, watermarks, mut errors) = self.0.watermarks(partitions, timeout)?;
+            let (failed, watermarks): (Vec<_>, _) =
+                watermarks.into_iter().partition(|w| w.partition == 1);
+            errors.extend(
+                failed
+                    .into_iter()
+                    .map(|w: scrape::Watermarks| scrape::PartitionError {
+                        topic: w.topic,
+                        partition: w.partition,
+                        error: "NotLeaderForPartition".into(),
+                    }),
+            );
+            Ok((now, watermarks, errors))
+        }
+
+        fn groups(&self, timeout: Duration) -> Result<Vec<String>> {
+            self.0.groups(timeout)
+        }
+
+        fn committed(
+            &self,
+            group: &str,
+            partitions: &[(&str, i32)],
+            timeout: Duration,
+        ) -> Result<(Instant, Vec<scrape::Committed>)> {
+            self.0.committed(group, partitions, timeout)
+        }
+
+        fn sample(
+            &self,
+            ranges: &[(&str, i32, i64, i64)],
+            timeout: Duration,
+        ) -> Result<Vec<scrape::Sample>> {
+            anyhow::ensure!(!self.1, "Broker: Topic authorization failed");
+            self.0.sample(ranges, timeout)
+        }
+
+        fn now(&self) -> Instant {
+            self.0.now()
+        }
+    }
+
+    #[test]
+    fn partition_failures_are_reported() {
+        let mut h = Harness::wrapping(vec![topic("flaky", 3, 6.)], vec![], |source| {
+            Box::new(Flaky(source, false))
+        });
+        h.rounds(3);
+        let stats = h.stats.current_stats(&"flaky".into()).unwrap();
+        assert_eq!(stats.skipped, 2);
+        // Only partition 2, which gets half the messages
+        assert_eq!(stats.rate, Some(3.));
+        // No broker is blamed for either
+        assert_eq!(h.stats.broker_failures, 0);
+        assert!(h.stats.broker_stats().iter().all(|b| b.down.is_none()));
+        assert_eq!(h.stats.metadata_error, None);
+        assert_eq!(
+            h.stats
+                .partition_errors
+                .get(&("flaky".into(), 1))
+                .map(|e| e.as_str()),
+            Some("NotLeaderForPartition")
+        );
+        assert_eq!(h.stats.partition_failures, 3);
+    }
+
+    #[test]
+    fn sampling_failures_are_reported() {
+        let mut h = Harness::wrapping(vec![topic("secret", 3, 6.)], vec![], |source| {
+            Box::new(Flaky(source, true))
+        });
+        h.rounds(3);
+        let err = h.stats.sample_error.as_deref().unwrap_or_default();
+        assert!(err.contains("Topic authorization failed"), "{}", err);
+        // Sizes and freshness, once the first round found something to read
+        assert_eq!(h.stats.sample_failures, 4);
+        // The cluster itself is fine
+        assert_eq!(h.stats.metadata_error, None);
+        assert_eq!(h.stats.metadata_failures, 0);
+    }
+
+    #[test]
+    fn finished_rounds_skip_history() {
+        let mut h = Harness::new(vec![topic("orders", 1, 1.)], vec![]);
+        h.stats.track_finished();
+        h.tx.send(scrape::Message::LoadingHistory).unwrap();
+        h.rounds(3);
+        h.tx.send(scrape::Message::HistoryLoaded).unwrap();
+        assert_eq!(h.stats.take_finished().len(), 0);
+        // Several rounds between two takes, as in a fast replay
+        let first = h.clock.now();
+        h.rounds(2);
+        h.stats.ingest().unwrap();
+        let finished = h.stats.take_finished();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0], ("orders".into(), first + INTERVAL));
+    }
+
+    #[test]
+    fn old_offsets_are_discarded() {
+        let mut h = Harness::new(vec![topic("long", 1, 1.)], vec![]);
+        h.rounds(400);
+        let stats = h.stats.current_stats(&"long".into()).unwrap();
+        assert_eq!(stats.total, 3990);
+        // Retention is an hour, plus one scrape interval
+        assert_eq!(stats.seen, 3610);
+
+        // Only the last minute is bucketed, though there's an hour of offsets
+        let now = h.clock.now();
+        let minute = Duration::from_secs(60);
+        let rates = h
+            .stats
+            .rates(&current("long"), (now, minute), INTERVAL, Unit::Messages)
+            .unwrap();
+        assert_eq!(rates.len(), 5);
+        assert!(rates.iter().all(|&(_, rate)| (rate - 1.).abs() < 1e-9));
+    }
+
+    #[test]
+    fn old_generations_are_dropped() {
+        let mut h = Harness::new(
+            vec![SyntheticTopic {
+                reset_every: Some(Duration::from_secs(600)),
+                ..topic("churn", 1, 1.)
+            }],
+            vec![],
+        );
+        h.rounds(600);
+        // Only generations with offsets from the last hour remain, but all resets are still counted
+        assert_eq!(h.stats.resets(&"churn".into()), 9);
+        assert_eq!(h.stats.sorted_basestats().len(), 7);
+    }
+
+    #[test]
+    fn deletion_rate_from_low_watermarks() {
+        let mut h = Harness::new(
+            vec![SyntheticTopic {
+                retention: Some(Duration::from_secs(30)),
+                ..topic("retained", 2, 6.)
+            }],
+            vec![],
+        );
+        h.rounds(10);
+        let stats = h.stats.current_stats(&"retained".into()).unwrap();
+        assert_eq!(stats.retained, 180);
+        // Retention deletes as fast as messages come in, 30 s later
+        assert!((stats.deletion_rate.unwrap() - 6.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn group_lag() {
+        let mut h = Harness::new(
+            vec![SyntheticTopic {
+                groups: vec![("slow".into(), Duration::from_secs(5))],
+                ..topic("consumed", 2, 12.)
+            }],
+            vec![],
+        );
+        h.rounds(4);
+        let stats = h.stats.current_stats(&"consumed".into()).unwrap();
+        let group = stats.laggiest_group().unwrap();
+        assert_eq!(group.group, "slow");
+        assert_eq!(group.lag, 60);
+        assert!((group.rate.unwrap() - 12.).abs() < 1e-9);
+    }
+
+    /// Offsets at seconds after `start`
+    fn polls(start: Instant, offsets: &[(u64, i64)]) -> BTreeMap<Instant, i64> {
+        offsets
+            .iter()
+            .map(|&(secs, offset)| (start + Duration::from_secs(secs), offset))
+            .collect()
+    }
+
+    fn bucketed(
+        padata: &[BTreeMap<Instant, i64>],
+        start: Instant,
+        (scraped, bucket): (u64, u64),
+    ) -> Vec<f64> {
+        let end = start + Duration::from_secs(scraped);
+        let padata = padata.iter().map(|polls| (1., polls));
+        let span = Duration::from_secs(scraped);
+        bucketed_rates(
+            padata,
+            (start, end),
+            (end, span),
+            Duration::from_secs(bucket),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|(_, rate)| rate)
+        .collect()
+    }
+
+    fn assert_rates(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn buckets_aligned_with_scrapes() {
+        let start = Instant::now();
+        let padata = [polls(start, &[(0, 0), (10, 100), (20, 300), (30, 300)])];
+        assert_rates(&bucketed(&padata, start, (30, 10)), &[10., 20., 0.]);
+    }
+
+    #[test]
+    fn scrape_interval_spans_buckets() {
+        let start = Instant::now();
+        let padata = [polls(start, &[(0, 0), (30, 150), (60, 450)])];
+        assert_rates(
+            &bucketed(&padata, start, (60, 10)),
+            &[5., 5., 5., 10., 10., 10.],
+        );
+    }
+
+    #[test]
+    fn partial_first_and_last_buckets() {
+        let start = Instant::now();
+        // 10/s for 15 s, then 1/s for 15 s. The middle bucket gets half of each.
+        let padata = [polls(start, &[(0, 0), (15, 150), (30, 165)])];
+        assert_rates(&bucketed(&padata, start, (30, 10)), &[10., 5.5, 1.]);
+    }
+
+    #[test]
+    fn several_scrapes_per_bucket() {
+        let start = Instant::now();
+        let offsets = (0..=6).map(|i| (i * 10, i as i64 * 30)).collect::<Vec<_>>();
+        let padata = [polls(start, &offsets)];
+        assert_rates(&bucketed(&padata, start, (60, 60)), &[3.]);
+        assert_rates(&bucketed(&padata, start, (60, 20)), &[3., 3., 3.]);
+    }
+
+    #[test]
+    fn scrape_gap_is_interpolated() {
+        let start = Instant::now();
+        // Nothing scraped between 20 s and 60 s, 80 messages arrived meanwhile
+        let padata = [polls(
+            start,
+            &[(0, 0), (10, 10), (20, 20), (60, 100), (70, 110)],
+        )];
+        assert_rates(
+            &bucketed(&padata, start, (70, 10)),
+            &[1., 1., 2., 2., 2., 2., 1.],
+        );
+    }
+
+    #[test]
+    fn partitions_add_up() {
+        let start = Instant::now();
+        let padata = [
+            polls(start, &[(0, 0), (10, 10), (20, 20)]),
+            polls(start, &[(0, 5), (10, 105), (20, 105)]),
+            // Only scraped once, contributes nothing
+            polls(start, &[(10, 1000)]),
+        ];
+        assert_rates(&bucketed(&padata, start, (20, 10)), &[11., 1.]);
+    }
+
+    #[test]
+    fn no_rates_before_scraping() {
+        let start = Instant::now();
+        let padata = [polls(start, &[(0, 0), (10, 10)])];
+        let end = start + Duration::from_secs(10);
+        let rates = bucketed_rates(
+            padata.iter().map(|polls| (1., polls)),
+            (end, end),
+            (start, Duration::from_secs(10)),
+            Duration::from_secs(10),
+        );
+        assert!(rates.is_none());
+        assert_rates(&bucketed(&padata, start, (5, 10)), &[]);
     }
 }
@@ -0,0 +1,299 @@
+use crate::{
+    scrape::{
+        BrokerInfo, Committed, Metadata, OffsetSource, PartitionError, PartitionInfo, Sample,
+        TopicInfo, Watermarks,
+    },
+    uses::*,
+};
+use std::f64::consts::TAU;
+
+/// A pretend cluster whose offsets are a function of time, for --demo and for tests
+pub struct Synthetic {
+    clock: Clock,
+    start: Instant,
+    brokers: i32,
+    topics: Vec<SyntheticTopic>,
+    names: Vec<TopicName>,
+    outages: Vec<Outage>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SyntheticTopic {
+    pub name: String,
+    pub partitions: i32,
+    /// Messages per second over all partitions. Partition n gets a share proportional to n + 1.
+    pub rate: f64,
+    /// Relative amplitude and period of a sine wave on top of the rate
+    pub wave: Option<(f64, Duration)>,
+    /// Delete and recreate the topic this often, starting over at offset 0
+    pub reset_every: Option<Duration>,
+    /// Delete messages older than this
+    pub retention: Option<Duration>,
+    /// Consumer groups, and how far they are behind the producers
+    pub groups: Vec<(String, Duration)>,
+    /// Bytes per message
+    pub message_size: u64,
+    /// How old messages already are when they're produced, like from a producer catching up on a backlog
+    pub delay: Duration,
+}
+
+/// A broker that doesn't answer offset queries for `length`, starting at `at`, every `every`
+#[derive(Debug, Clone)]
+pub struct Outage {
+    pub broker: i32,
+    pub at: Duration,
+    pub length: Duration,
+    pub every: Duration,
+}
+
+impl Synthetic {
+    /// Brokers are numbered from 1 to `brokers`
+    pub fn new(
+        clock: Clock,
+        brokers: i32,
+        topics: Vec<SyntheticTopic>,
+        outages: Vec<Outage>,
+    ) -> Self {
+        Synthetic {
+            start: clock.now(),
+            clock,
+            brokers: cmp::max(brokers, 1),
+            names: topics.iter().map(|t| TopicName::new(&t.name)).collect(),
+            topics,
+            outages,
+        }
+    }
+
+    pub fn demo(clock: Clock) -> Self {
+        let topic = |name: &str, partitions, rate, message_size| SyntheticTopic {
+            name: name.into(),
+            partitions,
+            rate,
+            message_size,
+            ..SyntheticTopic::default()
+        };
+        let groups = |groups: &[(&str, u64)]| {
+            groups
+                .iter()
+                .map(|&(group, lag)| (group.to_owned(), Duration::from_secs(lag)))
+                .collect()
+        };
+        let topics = vec![
+            SyntheticTopic {
+                wave: Some((0.6, Duration::from_secs(600))),
+                retention: Some(Duration::from_secs(1800)),
+                groups: groups(&[("billing", 5), ("analytics", 120)]),
+                ..topic("orders", 6, 120., 800)
+            },
+            SyntheticTopic {
+                groups: groups(&[("billing", 1)]),
+                ..topic("payments", 3, 25., 350)
+            },
+            SyntheticTopic {
+                wave: Some((0.3, Duration::from_secs(90))),
+                retention: Some(Duration::from_secs(600)),
+                groups: groups(&[("analytics", 30)]),
+                ..topic("clicks", 12, 900., 120)
+            },
+            SyntheticTopic {
+                delay: Duration::from_secs(3 * 3600),
+                ..topic("audit-log", 1, 0.5, 4000)
+            },
+            SyntheticTopic {
+                reset_every: Some(Duration::from_secs(600)),
+                ..topic("sandbox", 2, 40., 50)
+            },
+            topic("idle", 4, 0., 0),
+            topic("__consumer_offsets", 10, 3., 90),
+        ];
+        let outages = vec![Outage {
+            broker: 3,
+            at: Duration::from_secs(180),
+            length: Duration::from_secs(60),
+            every: Duration::from_secs(300),
+        }];
+        Synthetic::new(clock, 3, topics, outages)
+    }
+
+    fn elapsed(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.start)
+    }
+
+    fn down(&self, broker: i32, now: Instant) -> bool {
+        let elapsed = self.elapsed(now);
+        self.outages.iter().any(|outage| {
+            let phase = match outage.every.is_zero() {
+                true => elapsed,
+                false => {
+                    Duration::from_nanos((elapsed.as_nanos() % outage.every.as_nanos()) as u64)
+                }
+            };
+            outage.broker == broker && phase >= outage.at && phase < outage.at + outage.length
+        })
+    }
+
+    fn topic(&self, name: &str) -> Option<(usize, &SyntheticTopic)> {
+        self.topics.iter().enumerate().find(|(_, t)| t.name == name)
+    }
+
+    /// Replicas of a partition, leader first
+    fn replicas(&self, topic: usize, partition: i32) -> impl Iterator<Item = i32> + '_ {
+        (0..cmp::min(self.brokers, 3))
+            .map(move |k| 1 + (topic as i32 + partition + k) % self.brokers)
+    }
+}
+
+impl SyntheticTopic {
+    /// Age of the current incarnation of the topic
+    fn age(&self, elapsed: Duration) -> Duration {
+        match self.reset_every {
+            Some(every) if !every.is_zero() => {
+                Duration::from_nanos((elapsed.as_nanos() % every.as_nanos()) as u64)
+            }
+            _ => elapsed,
+        }
+    }
+
+    /// Messages produced to a partition up to a given age of the topic
+    fn produced(&self, partition: i32, age: Duration) -> i64 {
+        let share = (partition + 1) as f64 / (self.partitions * (self.partitions + 1) / 2) as f64;
+        let t = age.as_secs_f64();
+        let total = match self.wave {
+            Some((amplitude, period)) if !period.is_zero() => {
+                let period = period.as_secs_f64();
+                t + amplitude * period / TAU * (1. - (TAU * t / period).cos())
+            }
+            _ => t,
+        };
+        (self.rate * share * total) as i64
+    }
+
+    fn watermarks(&self, partition: i32, elapsed: Duration) -> (i64, i64) {
+        let age = self.age(elapsed);
+        let low = self.retention.map_or(0, |retention| {
+            self.produced(partition, age.saturating_sub(retention))
+        });
+        (low, self.produced(partition, age))
+    }
+}
+
+impl OffsetSource for Synthetic {
+    fn metadata(&self, _timeout: Duration) -> Result<Metadata> {
+        let now = self.now();
+        Ok(Metadata {
+            brokers: (1..=self.brokers)
+                .map(|id| BrokerInfo {
+                    id,
+                    host: format!("synthetic-{}:9092", id),
+                })
+                .collect(),
+            topics: self
+                .topics
+                .iter()
+                .enumerate()
+                .map(|(idx, topic)| TopicInfo {
+                    name: self.names[idx].clone(),
+                    partitions: (0..topic.partitions)
+                        .map(|id| PartitionInfo {
+                            id,
+                            leader: self.replicas(idx, id).next().unwrap_or(1),
+                            isr: self
+                                .replicas(idx, id)
+                                .filter(|&broker| !self.down(broker, now))
+                                .collect(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+
+    fn watermarks(
+        &self,
+        partitions: &[(&str, i32)],
+        _timeout: Duration,
+    ) -> Result<(Instant, Vec<Watermarks>, Vec<PartitionError>)> {
+        let now = self.now();
+        let mut watermarks = Vec::with_capacity(partitions.len());
+        for &(name, partition) in partitions {
+            let (idx, topic) = match self.topic(name) {
+                Some(topic) if partition < topic.1.partitions => topic,
+                _ => continue,
+            };
+            if let Some(leader) = self.replicas(idx, partition).next() {
+                if self.down(leader, now) {
+                    anyhow::bail!("Broker {} is not answering (synthetic outage)", leader);
+                }
+            }
+            let (low, high) = topic.watermarks(partition, self.elapsed(now));
+            watermarks.push(Watermarks {
+                topic: self.names[idx].clone(),
+                partition,
+                low,
+                high,
+            });
+        }
+        Ok((now, watermarks, vec![]))
+    }
+
+    fn groups(&self, _timeout: Duration) -> Result<Vec<String>> {
+        Ok(self
+            .topics
+            .iter()
+            .flat_map(|topic| topic.groups.iter().map(|(group, _)| group.clone()))
+            .sorted()
+            .dedup()
+            .collect())
+    }
+
+    fn committed(
+        &self,
+        group: &str,
+        partitions: &[(&str, i32)],
+        _timeout: Duration,
+    ) -> Result<(Instant, Vec<Committed>)> {
+        let now = self.now();
+        let elapsed = self.elapsed(now);
+        let committed = partitions
+            .iter()
+            .filter_map(|&(name, partition)| {
+                let (idx, topic) = self.topic(name)?;
+                let (_, lag) = topic.groups.iter().find(|(g, _)| g == group)?;
+                let age = topic.age(elapsed).saturating_sub(*lag);
+                Some(Committed {
+                    topic: self.names[idx].clone(),
+                    partition,
+                    offset: topic.produced(partition, age),
+                })
+            })
+            .collect();
+        Ok((now, committed))
+    }
+
+    fn sample(&self, ranges: &[(&str, i32, i64, i64)], _timeout: Duration) -> Result<Vec<Sample>> {
+        let now = self.now();
+        Ok(ranges
+            .iter()
+            .filter_map(|&(name, partition, from, to)| {
+                let (idx, topic) = self.topic(name)?;
+                let leader = self.replicas(idx, partition).next()?;
+                if self.down(leader, now) || to <= from {
+                    return None;
+                }
+                let produced =
+                    self.clock.wall_at(now) - chrono::Duration::from_std(topic.delay).ok()?;
+                Some(Sample {
+                    topic: self.names[idx].clone(),
+                    partition,
+                    messages: to - from,
+                    bytes: (to - from) as u64 * topic.message_size,
+                    newest_timestamp: Some(produced.timestamp_millis()),
+                })
+            })
+            .collect())
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+}
@@ -3,16 +3,19 @@ use crossterm::{
     terminal::enable_raw_mode,
 };
 use tui::{
-    backend::CrosstermBackend,
-    layout::{Alignment, Constraint, Direction, Layout},
+    backend::{Backend, CrosstermBackend},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     symbols,
     text::{Span, Spans},
-    widgets::{Axis, Block, Cell, Chart, Dataset, GraphType, Paragraph, Row, Table, Wrap},
-    Terminal,
+    widgets::{
+        Axis, Block, Borders, Cell, Chart, Dataset, GraphType, Paragraph, Row, Table, TableState,
+        Wrap,
+    },
+    Frame, Terminal,
 };
 
-use crate::uses::*;
+use crate::{alerts::Alert, uses::*};
 
 pub(crate) fn run(opts: &Opts, mut scraper: Stats) -> Result<()> {
     enable_raw_mode()?;
@@ -24,12 +27,17 @@ pub(crate) fn run(opts: &Opts, mut scraper: Stats) -> Result<()> {
     let mut color_assignment = ColorAssignment::new();
     let mut redraw = true;
     let mut last_draw = Instant::now();
+    let mut show_consumers = false;
+    let mut selection = Selection::default();
+    let mut screen = Screen::Overview;
+    let mut draw_interval = opts.draw_interval;
+    let mut show_brokers = false;
+    let mut expanded = HashSet::new();
+    let mut show_total = false;
+    let mut chart_mode = ChartMode::default();
     loop {
         let now = Instant::now();
         redraw |= scraper.ingest()?;
-        if let Some(discard) = now.checked_sub(opts.draw_interval.mul_f64(1.1)) {
-            scraper.discard_before(discard)
-        }
         if now.duration_since(last_draw) > Duration::from_secs(1) {
             redraw |= true;
             last_draw = now;
@@ -38,84 +46,202 @@ pub(crate) fn run(opts: &Opts, mut scraper: Stats) -> Result<()> {
         if redraw {
             redraw = false;
             terminal.draw(|f| {
-                let mut basestats = scraper.basestats().collect::<Vec<_>>();
-                basestats.sort_by_key(|s| (s.topic.stat_idx, cmp::Reverse((s.seen, s.total))));
-                let basestats = basestats;
+                let basestats = overview_rows(&scraper, &expanded);
                 color_assignment.compute(&basestats);
+                selection.update(&basestats);
 
-                let content_box;
+                let mut status = Vec::new();
+                if let Clock::Replay(replay) = scraper.clock() {
+                    status.push(Span::raw(format!(
+                        "Replay: {} at {}x ",
+                        format_time(replay.wall_now(), TimeFormat::Full),
+                        replay.speed()
+                    )));
+                }
                 if let Some(err) = scraper.metadata_error.as_ref() {
-                    let text = vec![Spans::from(vec![Span::styled(
+                    status.push(Span::styled(
                         err.to_string(),
                         Style::default().fg(Color::Red),
-                    )])];
+                    ));
+                }
+                if let Some(err) = scraper.sample_error.as_ref() {
+                    if !status.is_empty() {
+                        status.push(Span::raw(" "));
+                    }
+                    status.push(Span::styled(
+                        format!("Sampling: {}", err),
+                        Style::default().fg(Color::Yellow),
+                    ));
+                }
+                if let Some(text) = partition_errors(&scraper.partition_errors) {
+                    if !status.is_empty() {
+                        status.push(Span::raw(" "));
+                    }
+                    status.push(Span::styled(text, Style::default().fg(Color::Yellow)));
+                }
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Length(1), Constraint::Min(2)])
+                    .split(f.size());
+                f.render_widget(
+                    mk_header(&scraper.cluster_stats(), scraper.clock()),
+                    chunks[0],
+                );
+                let content_box = chunks[1];
+                let content_box = if !status.is_empty() {
+                    let text = vec![Spans::from(status)];
                     let paragraph = Paragraph::new(text)
                         .alignment(Alignment::Center)
                         .wrap(Wrap { trim: true });
                     let chunks = Layout::default()
                         .direction(Direction::Vertical)
                         .constraints([Constraint::Min(2), Constraint::Length(1)])
-                        .split(f.size());
+                        .split(content_box);
                     f.render_widget(paragraph, chunks[1]);
-                    content_box = chunks[0];
+                    chunks[0]
                 } else {
-                    content_box = f.size();
-                }
+                    content_box
+                };
 
-                let chunks = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints([Constraint::Min(10), Constraint::Length(46)])
-                    .split(content_box);
-
-                f.render_widget(mk_table(&basestats, &color_assignment), chunks[1]);
-
-                let width = chunks[0].width.saturating_sub(9);
-                let height = chunks[0].height.saturating_sub(2);
-                if cmp::min(width, height) <= 2 {
-                    f.render_widget(
-                        Paragraph::new(vec![Spans::from("too small"); chunks[0].height as usize])
-                            .alignment(Alignment::Center),
-                        chunks[0],
-                    );
-                } else {
-                    let bucket_size = opts.draw_interval / (width as u32 * 2);
-                    let drawn_topic_names = color_assignment
-                        .colored_topic_names()
-                        .collect::<HashSet<_>>();
-                    let (now_date, data) = mk_chart_data(
-                        bucket_size,
-                        basestats
-                            .iter()
-                            .filter(|stat| drawn_topic_names.contains(&stat.topic.name.as_ref())),
-                        &scraper,
-                        &mut maxy,
-                    );
-                    if data.len() > 0 {
-                        let chart = mk_chart(
-                            width,
-                            height,
-                            &data,
-                            now_date,
-                            opts.draw_interval,
-                            &color_assignment,
-                            maxy,
+                match &screen {
+                    Screen::Overview => {
+                        let columns = Columns::for_stats(&basestats);
+                        let chunks = Layout::default()
+                            .direction(Direction::Horizontal)
+                            .constraints([Constraint::Min(10), Constraint::Length(columns.width())])
+                            .split(content_box);
+
+                        let alerts = scraper.alerts().collect::<Vec<_>>();
+                        let table_box = match alerts.is_empty() {
+                            false => {
+                                let table_chunks = Layout::default()
+                                    .direction(Direction::Vertical)
+                                    .constraints([
+                                        Constraint::Min(3),
+                                        Constraint::Length(cmp::min(
+                                            alerts.len() as u16 + 2,
+                                            chunks[1].height / 3,
+                                        )),
+                                    ])
+                                    .split(chunks[1]);
+                                f.render_widget(
+                                    mk_alert_table(&alerts, scraper.clock().now()),
+                                    table_chunks[1],
+                                );
+                                table_chunks[0]
+                            }
+                            true => chunks[1],
+                        };
+
+                        let brokers = scraper.broker_stats();
+                        let table_box =
+                            match show_brokers || brokers.iter().any(|b| b.down.is_some()) {
+                                true => {
+                                    let table_chunks = Layout::default()
+                                        .direction(Direction::Vertical)
+                                        .constraints([
+                                            Constraint::Min(3),
+                                            Constraint::Length(cmp::min(
+                                                brokers.len() as u16 + 2,
+                                                table_box.height / 3,
+                                            )),
+                                        ])
+                                        .split(table_box);
+                                    f.render_widget(
+                                        mk_broker_table(&brokers, scraper.clock().now()),
+                                        table_chunks[1],
+                                    );
+                                    table_chunks[0]
+                                }
+                                false => table_box,
+                            };
+
+                        selection.page = table_box.height.saturating_sub(1).into();
+                        f.render_stateful_widget(
+                            mk_table(
+                                &basestats,
+                                &scraper,
+                                &color_assignment,
+                                columns,
+                                &expanded,
+                                opts.stale_after,
+                            ),
+                            table_box,
+                            &mut selection.state,
+                        );
+
+                        draw_chart(
+                            f,
+                            chunks[0],
+                            draw_interval,
+                            chart_mode,
+                            &mut maxy,
+                            scraper.clock(),
+                            |bucket_size, window| {
+                                let mut data = mk_chart_data(
+                                    bucket_size,
+                                    window,
+                                    basestats.iter().filter(|stat| {
+                                        color_assignment.colors_name(&stat.topic.name)
+                                    }),
+                                    &scraper,
+                                    &color_assignment,
+                                    show_consumers,
+                                    chart_mode.unit,
+                                );
+                                if show_total {
+                                    data.extend(
+                                        scraper
+                                            .total_rates(window, bucket_size, chart_mode.unit)
+                                            .map(|data| ChartLine {
+                                                name: "total".to_owned(),
+                                                color: Color::White,
+                                                marker: symbols::Marker::Braille,
+                                                stacked: false,
+                                                data,
+                                            }),
+                                    );
+                                }
+                                data
+                            },
                         );
-                        f.render_widget(chart, chunks[0]);
-                    } else {
-                        let text = vec![Spans::from(vec![Span::raw("[no plottable data]")])];
-                        let paragraph = Paragraph::new(text)
-                            .alignment(Alignment::Center)
-                            .block(Block::default())
-                            .wrap(Wrap { trim: true });
-                        let vsplit_chunks = Layout::default()
+                    }
+                    Screen::Partitions(topic) => {
+                        let partstats = scraper.partition_stats(topic);
+                        let chunks = Layout::default()
+                            .direction(Direction::Horizontal)
+                            .constraints([Constraint::Min(10), Constraint::Length(46)])
+                            .split(content_box);
+                        let table_chunks = Layout::default()
                             .direction(Direction::Vertical)
-                            .constraints([
-                                Constraint::Percentage(50),
-                                Constraint::Min(1),
-                                Constraint::Percentage(50),
-                            ])
-                            .split(chunks[0]);
-                        f.render_widget(paragraph, vsplit_chunks[1])
+                            .constraints([Constraint::Length(1), Constraint::Min(1)])
+                            .split(chunks[1]);
+                        f.render_widget(
+                            Paragraph::new(Span::styled(
+                                topic.name.as_str(),
+                                Style::default().add_modifier(Modifier::BOLD),
+                            )),
+                            table_chunks[0],
+                        );
+                        f.render_widget(mk_partition_table(&partstats), table_chunks[1]);
+                        draw_chart(
+                            f,
+                            chunks[0],
+                            draw_interval,
+                            chart_mode,
+                            &mut maxy,
+                            scraper.clock(),
+                            |bucket_size, window| {
+                                mk_partition_chart_data(
+                                    bucket_size,
+                                    window,
+                                    topic,
+                                    &partstats,
+                                    &scraper,
+                                    chart_mode.unit,
+                                )
+                            },
+                        );
                     }
                 }
             })?;
@@ -130,6 +256,54 @@ pub(crate) fn run(opts: &Opts, mut scraper: Stats) -> Result<()> {
                     ..
                 })) => match (code, modifiers) {
                     (KeyCode::Char('q'), _) => break,
+                    (KeyCode::Char('c'), KeyModifiers::NONE) => show_consumers ^= true,
+                    (KeyCode::Char('b'), _) => show_brokers ^= true,
+                    (KeyCode::Char('t'), _) => show_total ^= true,
+                    (KeyCode::Char('l'), _) => chart_mode.log ^= true,
+                    (KeyCode::Char('s'), _) => chart_mode.stacked ^= true,
+                    (KeyCode::Char('u'), _) => {
+                        chart_mode.unit = match chart_mode.unit {
+                            stats::Unit::Messages => stats::Unit::Bytes,
+                            stats::Unit::Bytes => stats::Unit::Messages,
+                        }
+                    }
+                    (KeyCode::Char(c @ ('<' | '>' | '[' | ']')), _) => {
+                        if let Clock::Replay(replay) = scraper.clock() {
+                            match c {
+                                '<' => replay.set_speed((replay.speed() / 2.).max(1. / 64.)),
+                                '>' => replay.set_speed((replay.speed() * 2.).min(4096.)),
+                                '[' => replay.seek(draw_interval / 4, true),
+                                _ => replay.seek(draw_interval / 4, false),
+                            }
+                        }
+                    }
+                    (KeyCode::Char('+'), _) => draw_interval = zoom_in(draw_interval),
+                    (KeyCode::Char('-'), _) => draw_interval = zoom_out(draw_interval),
+                    (KeyCode::Up | KeyCode::Char('k'), _) => selection.step(-1),
+                    (KeyCode::Down | KeyCode::Char('j'), _) => selection.step(1),
+                    (KeyCode::PageUp, _) => selection.step(-(selection.page as isize)),
+                    (KeyCode::PageDown, _) => selection.step(selection.page as isize),
+                    (KeyCode::Home, _) => selection.step(isize::MIN),
+                    (KeyCode::End, _) => selection.step(isize::MAX),
+                    (KeyCode::Enter, _) => match &selection.topic {
+                        Some(topic) if scraper.is_aggregate(topic) => {
+                            let collapsed = !expanded.remove(&topic.name);
+                            if collapsed {
+                                expanded.insert(topic.name.clone());
+                            }
+                        }
+                        Some(topic) => screen = Screen::Partitions(topic.clone()),
+                        None => (),
+                    },
+                    (KeyCode::Esc | KeyCode::Backspace, _) => match screen {
+                        Screen::Partitions(_) => screen = Screen::Overview,
+                        Screen::Overview => selection.topic = None,
+                    },
+                    (KeyCode::Char(' ') | KeyCode::Char('p'), _) => {
+                        if let Some(topic) = &selection.topic {
+                            color_assignment.toggle_pin(topic);
+                        }
+                    }
                     (KeyCode::Char('c'), KeyModifiers::CONTROL) => break,
                     (KeyCode::Char('d'), KeyModifiers::CONTROL) => break,
                     (_, _) => (),
@@ -142,33 +316,241 @@ pub(crate) fn run(opts: &Opts, mut scraper: Stats) -> Result<()> {
     Ok(())
 }
 
-fn mk_chart<'a>(
+/// One line summary of the whole cluster
+fn mk_header<'a>(cluster: &stats::ClusterStats, clock: &Clock) -> Paragraph<'a> {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let last_scrape = cluster
+        .last_scrape
+        .map(|at| format_time(clock.wall_at(at), TimeFormat::Seconds))
+        .unwrap_or_else(|| "never".to_owned());
+    Paragraph::new(Spans::from(vec![
+        Span::styled(
+            cluster
+                .rate
+                .map(format_number)
+                .unwrap_or_else(|| "-".to_owned()),
+            bold,
+        ),
+        Span::raw(" msgs/s │ "),
+        Span::styled(
+            cluster.byte_rate.map(format_number).unwrap_or_default(),
+            bold,
+        ),
+        Span::raw(match cluster.byte_rate {
+            Some(_) => " bytes/s │ ",
+            None => "",
+        }),
+        Span::styled(cluster.topics.to_string(), bold),
+        Span::raw(" topics │ "),
+        Span::styled(cluster.partitions.to_string(), bold),
+        Span::raw(" partitions │ "),
+        Span::styled(cluster.brokers.to_string(), bold),
+        Span::raw(" brokers │ last scrape "),
+        Span::styled(last_scrape, bold),
+    ]))
+    .style(Style::default().fg(Color::White))
+}
+
+/// Topics and aggregates, with grouped topics only showing up below their expanded aggregate
+fn overview_rows(scraper: &Stats, expanded: &HashSet<TopicName>) -> Vec<stats::TopicStats> {
+    let mut rows = scraper.sorted_basestats();
+    let aggregates = scraper.aggregate_stats();
+    if aggregates.is_empty() {
+        return rows;
+    }
+    rows.retain(|s| scraper.aggregate_of(&s.topic).is_none());
+    let mut members = HashMap::new();
+    for stats::AggregateStats { stats, members: m } in aggregates {
+        if expanded.contains(&stats.topic.name) {
+            members.insert(stats.topic.clone(), m);
+        }
+        rows.push(stats);
+    }
+    stats::sort_by_activity(&mut rows);
+    rows.into_iter()
+        .flat_map(|row| {
+            let members = members.remove(&row.topic).unwrap_or_default();
+            once(row).chain(members)
+        })
+        .collect()
+}
+
+/// Table cursor. Tracked by topic, so it sticks to its row when the table gets re-sorted.
+#[derive(Default)]
+struct Selection {
+    topic: Option<Topic>,
+    /// Topics in the order they were last drawn
+    rows: Vec<Topic>,
+    /// Number of visible table rows
+    page: usize,
+    state: TableState,
+}
+
+impl Selection {
+    fn update(&mut self, basestats: &[stats::TopicStats]) {
+        self.rows = basestats.iter().map(|s| s.topic.clone()).collect();
+        let idx = self
+            .topic
+            .as_ref()
+            .and_then(|topic| self.rows.iter().position(|row| row == topic));
+        if idx.is_none() {
+            // Topic vanished
+            self.topic = None;
+        }
+        self.state.select(idx);
+    }
+
+    fn step(&mut self, delta: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let idx = match self.state.selected() {
+            Some(idx) => (idx as isize).saturating_add(delta),
+            None if delta < 0 => self.rows.len() as isize - 1,
+            None => 0,
+        };
+        let idx = idx.clamp(0, self.rows.len() as isize - 1) as usize;
+        self.topic = Some(self.rows[idx].clone());
+        self.state.select(Some(idx));
+    }
+}
+
+enum Screen {
+    Overview,
+    /// Drill-down into a single topic
+    Partitions(Topic),
+}
+
+struct ChartLine {
+    name: String,
+    color: Color,
+    marker: symbols::Marker,
+    /// Part of the stack in stacked mode, as opposed to consumer or total lines
+    stacked: bool,
+    data: Vec<(f64, f64)>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct ChartMode {
+    /// Logarithmic y axis
+    log: bool,
+    /// Each line on top of the previous ones, showing their contribution to the sum
+    stacked: bool,
+    unit: stats::Unit,
+}
+
+impl ChartMode {
+    /// Position on the y axis of a rate
+    fn scale(self, v: f64) -> f64 {
+        match self.log {
+            true => (1. + v.max(0.)).log10(),
+            false => v,
+        }
+    }
+
+    /// Rate at a position on the y axis
+    fn unscale(self, y: f64) -> f64 {
+        match self.log {
+            true => 10f64.powf(y) - 1.,
+            false => y,
+        }
+    }
+}
+
+/// Draws the chart, or a placeholder if there is nothing to draw
+fn draw_chart<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    draw_interval: Duration,
+    mode: ChartMode,
+    maxy: &mut f64,
+    clock: &Clock,
+    mk_data: impl FnOnce(Duration, (Instant, Duration)) -> Vec<ChartLine>,
+) {
+    let width = area.width.saturating_sub(9);
+    let height = area.height.saturating_sub(2);
+    if cmp::min(width, height) <= 2 {
+        f.render_widget(
+            Paragraph::new(vec![Spans::from("too small"); area.height as usize])
+                .alignment(Alignment::Center),
+            area,
+        );
+        return;
+    }
+    let bucket_size = draw_interval / (width as u32 * 2);
+    let now_date = clock.wall_now();
+    let mut data = mk_data(bucket_size, (clock.now(), draw_interval));
+    if !data.is_empty() {
+        if mode.stacked {
+            stack(&mut data, bucket_size);
+        }
+        rescale(&data, maxy);
+        for (_, v) in data.iter_mut().flat_map(|line| &mut line.data) {
+            *v = mode.scale(*v);
+        }
+        let chart = mk_chart(width, height, &data, now_date, draw_interval, mode, *maxy);
+        f.render_widget(chart, area);
+    } else {
+        let text = vec![Spans::from(vec![Span::raw("[no plottable data]")])];
+        let paragraph = Paragraph::new(text)
+            .alignment(Alignment::Center)
+            .block(Block::default())
+            .wrap(Wrap { trim: true });
+        let vsplit_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Percentage(50),
+                Constraint::Min(1),
+                Constraint::Percentage(50),
+            ])
+            .split(area);
+        f.render_widget(paragraph, vsplit_chunks[1])
+    }
+}
+
+fn mk_chart(
     width: u16,
     height: u16,
-    data: &'a [(&'a Topic, Vec<(f64, f64)>)],
+    data: &[ChartLine],
     now_date: DateTime<Local>,
     draw_interval: Duration,
-    color_assignment: &ColorAssignment,
+    mode: ChartMode,
     maxy: f64,
-) -> Chart<'a> {
+) -> Chart<'_> {
     let data = data
         .iter()
-        .map(|(topic, data)| {
-            Dataset::default()
-                .name(topic.name.to_owned())
-                .marker(symbols::Marker::Braille)
-                .graph_type(GraphType::Line)
-                .style(Style::default().fg(color_assignment.get(topic)))
-                .data(data)
-        })
+        .map(
+            |ChartLine {
+                 name,
+                 color,
+                 marker,
+                 data,
+                 ..
+             }| {
+                Dataset::default()
+                    .name(name.as_str())
+                    .marker(*marker)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(*color))
+                    .data(data)
+            },
+        )
         .collect();
-    let long_time = draw_interval > Duration::from_secs(3600 * 6);
-    let date_length = match long_time {
-        true => 19,
-        false => 8,
-    };
+    let time_format = TimeFormat::for_interval(draw_interval);
+    let date_length = time_format.len();
     let space = 5;
     let maxl = cmp::max(height / 10, 1);
+    let top = mode.scale(maxy);
+    let unit = match mode.unit {
+        stats::Unit::Messages => "Msgs / s",
+        stats::Unit::Bytes => "Bytes / s",
+    };
+    let title = match (mode.log, mode.stacked) {
+        (false, false) => unit.to_owned(),
+        (true, false) => format!("{} (log)", unit),
+        (false, true) => format!("{} (stacked)", unit),
+        (true, true) => format!("{} (stacked, log)", unit),
+    };
     let chart = Chart::new(data)
         .hidden_legend_constraints((Constraint::Percentage(0), Constraint::Percentage(0)))
         .x_axis(
@@ -182,7 +564,9 @@ fn mk_chart<'a>(
                                 chrono::Duration::from_std(
                                     draw_interval.mul_f64(1. - i as f64 / width as f64),
                                 )
-                                .map(|dur: chrono::Duration| format_time(now_date - dur, long_time))
+                                .map(|dur: chrono::Duration| {
+                                    format_time(now_date - dur, time_format)
+                                })
                                 .unwrap_or("X".repeat(date_length)),
                             )
                         }))
@@ -191,82 +575,325 @@ fn mk_chart<'a>(
         )
         .y_axis(
             Axis::default()
-                .title(Span::styled("Msgs / s", Style::default().fg(Color::Red)))
+                .title(Span::styled(title, Style::default().fg(Color::Red)))
                 .style(Style::default().fg(Color::White))
-                .bounds([0.0, maxy])
+                .bounds([0.0, top])
                 .labels(
-                    (0..=maxl)
-                        .map(|p| Span::from(format_number(p as f64 / maxl as f64 * maxy)))
+                    y_labels(mode, maxy, maxl)
+                        .into_iter()
+                        .map(Span::from)
                         .collect(),
                 ),
         );
     chart
 }
 
+/// Rates at `steps` evenly spaced heights from 0 to the top of a chart going up to `maxy`
+fn y_labels(mode: ChartMode, maxy: f64, steps: u16) -> Vec<String> {
+    let top = mode.scale(maxy);
+    (0..=steps)
+        .map(|p| format_number(mode.unscale(p as f64 / steps as f64 * top)))
+        .collect()
+}
+
 fn mk_chart_data<'a>(
     bucket_size: Duration,
+    window: (Instant, Duration),
     basestats: impl Iterator<Item = &'a stats::TopicStats>,
     scraper: &Stats,
-    maxy: &mut f64,
-) -> (DateTime<Local>, Vec<(&'a Topic, Vec<(f64, f64)>)>) {
-    let now_date = Local::now();
-    let now = Instant::now();
-    let data = basestats
-        .filter(|t| t.seen > 0)
-        .filter_map(|stats::TopicStats { topic, .. }| {
-            Some((topic, scraper.rates(topic, now, bucket_size)?))
+    color_assignment: &ColorAssignment,
+    show_consumers: bool,
+    unit: stats::Unit,
+) -> Vec<ChartLine> {
+    basestats
+        .flat_map(|stats::TopicStats { topic, groups, .. }| {
+            let color = color_assignment.get(topic);
+            let produced = scraper
+                .rates(topic, window, bucket_size, unit)
+                .map(|data| ChartLine {
+                    name: topic.name.to_string(),
+                    color,
+                    marker: symbols::Marker::Braille,
+                    stacked: true,
+                    data,
+                });
+            let consumed = groups.iter().filter(move |_| show_consumers).filter_map(
+                move |stats::GroupStats { group, .. }| {
+                    Some(ChartLine {
+                        name: format!("{} ({})", topic.name, group),
+                        color,
+                        marker: symbols::Marker::Dot,
+                        stacked: false,
+                        data: scraper.consume_rates(topic, group, window, bucket_size, unit)?,
+                    })
+                },
+            );
+            produced.into_iter().chain(consumed)
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
+
+fn mk_partition_chart_data(
+    bucket_size: Duration,
+    window: (Instant, Duration),
+    topic: &Topic,
+    partstats: &[stats::PartitionStats],
+    scraper: &Stats,
+    unit: stats::Unit,
+) -> Vec<ChartLine> {
+    partstats
+        .iter()
+        .filter_map(|p| {
+            Some(ChartLine {
+                name: p.partition.to_string(),
+                color: partition_color(p.partition),
+                marker: symbols::Marker::Braille,
+                stacked: true,
+                data: scraper.partition_rates(topic, p.partition, window, bucket_size, unit)?,
+            })
+        })
+        .collect()
+}
+
+fn partition_color(partition: i32) -> Color {
+    const PALETTE: [Color; 12] = [
+        Color::Blue,
+        Color::Yellow,
+        Color::Red,
+        Color::Green,
+        Color::Magenta,
+        Color::Cyan,
+        Color::LightBlue,
+        Color::LightYellow,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::LightMagenta,
+        Color::LightCyan,
+    ];
+    PALETTE[partition.rem_euclid(PALETTE.len() as i32) as usize]
+}
+
+/// Add each stacked line to the ones before it.
+/// Lines don't necessarily start at the same time, so points are matched up by bucket.
+fn stack(data: &mut [ChartLine], bucket_size: Duration) {
+    let bucket_size = bucket_size.as_secs_f64();
+    let mut below = HashMap::<i64, f64>::new();
+    for line in data.iter_mut().filter(|line| line.stacked) {
+        for (x, v) in &mut line.data {
+            let sum = below.entry((*x / bucket_size).round() as i64).or_default();
+            *sum += *v;
+            *v = *sum;
+        }
+    }
+}
+
+/// Adjust the y axis bound if the data has moved out of view, or is only using a small portion of it
+fn rescale(data: &[ChartLine], maxy: &mut f64) {
     let maxv = data
         .iter()
-        .flat_map(|(_, data)| data.iter().map(|(_, v)| *v))
+        .flat_map(|line| line.data.iter().map(|(_, v)| *v))
         .max_by(|a, b| a.partial_cmp(b).unwrap_or(a.is_nan().cmp(&b.is_nan())))
         .unwrap_or(1f64);
     if *maxy < maxv || *maxy > 1.5 * maxv {
         *maxy = maxv * 1.25;
     }
-    (now_date, data)
+}
+
+/// The optional columns are all the same width, so any of them can be left out
+const TABLE_WIDTHS: [Constraint; 9] = [
+    Constraint::Length(30),
+    Constraint::Length(7),
+    Constraint::Length(8),
+    Constraint::Length(7),
+    Constraint::Length(7),
+    Constraint::Length(7),
+    Constraint::Length(7),
+    Constraint::Length(7),
+    Constraint::Length(7),
+];
+
+/// Optional columns of the topic table, shown if any topic has something to show in them
+#[derive(Clone, Copy)]
+struct Columns {
+    deletes: bool,
+    bytes: bool,
+    age: bool,
+    groups: bool,
+}
+
+impl Columns {
+    fn for_stats(basestats: &[stats::TopicStats]) -> Self {
+        Columns {
+            deletes: basestats
+                .iter()
+                .any(|s| s.deletion_rate.map_or(false, |rate| rate > 0.)),
+            bytes: basestats.iter().any(|s| s.byte_rate.is_some()),
+            age: basestats.iter().any(|s| s.newest_message.is_some()),
+            groups: basestats.iter().any(|s| !s.groups.is_empty()),
+        }
+    }
+
+    fn width(self) -> u16 {
+        55 + 8 * self.deletes as u16
+            + 8 * self.bytes as u16
+            + 8 * self.age as u16
+            + 16 * self.groups as u16
+    }
 }
 
 fn mk_table<'a>(
-    basestats: &'a Vec<stats::TopicStats>,
+    basestats: &'a [stats::TopicStats],
+    scraper: &Stats,
     color_assignment: &ColorAssignment,
+    columns: Columns,
+    expanded: &HashSet<TopicName>,
+    stale_after: Duration,
 ) -> Table<'a> {
-    Table::new(basestats.iter().map(
-        |stats::TopicStats {
-             topic, total, rate, ..
-         }| {
-            Row::new(vec![
-                Cell::from(Span::styled(
-                    &topic.name,
-                    Style::default().fg(color_assignment.get(topic)),
-                )),
-                Cell::from(right_align(format_number(*total as f64), 7)),
-                Cell::from(right_align(rate.map(format_number).unwrap_or_default(), 7)),
-            ])
-        },
-    ))
+    let mut header = vec!["Topic", "Total", "Retained", "Per Sec"];
+    if columns.deletes {
+        header.push("Del/s");
+    }
+    if columns.bytes {
+        header.push("Bytes/s");
+    }
+    if columns.age {
+        header.push("Age");
+    }
+    if columns.groups {
+        header.extend(["Lag", "Cons/s"]);
+    }
+    let header_len = header.len();
+    Table::new(basestats.iter().map(|stats| {
+        let stats::TopicStats {
+            topic,
+            total,
+            retained,
+            rate,
+            deletion_rate,
+            skipped,
+            byte_rate,
+            ..
+        } = stats;
+        let mut name_style = Style::default().fg(color_assignment.get(topic));
+        if color_assignment.is_pinned(topic) {
+            name_style = name_style.add_modifier(Modifier::UNDERLINED);
+        }
+        let indent = match (scraper.is_aggregate(topic), scraper.aggregate_of(topic)) {
+            (true, _) if expanded.contains(&topic.name) => "▾ ",
+            (true, _) => "▸ ",
+            (false, Some(_)) => "  ",
+            (false, None) => "",
+        };
+        let name = match (indent, skipped) {
+            ("", 0) => Cow::from(topic.name.as_str()),
+            (indent, 0) => Cow::from(format!("{}{}", indent, topic.name)),
+            (indent, _) => Cow::from(format!("{}{} (incomplete)", indent, topic.name)),
+        };
+        let mut cells = vec![
+            Cell::from(Span::styled(name, name_style)),
+            Cell::from(right_align(format_number(*total as f64), 7)),
+            Cell::from(right_align(format_number(*retained as f64), 8)),
+            Cell::from(right_align(rate.map(format_number).unwrap_or_default(), 7)),
+        ];
+        if columns.deletes {
+            cells.push(Cell::from(right_align(
+                deletion_rate.map(format_number).unwrap_or_default(),
+                7,
+            )));
+        }
+        if columns.bytes {
+            cells.push(Cell::from(right_align(
+                byte_rate.map(format_number).unwrap_or_default(),
+                7,
+            )));
+        }
+        if columns.age {
+            cells.push(match scraper.message_age(stats) {
+                Some(age) => {
+                    let style = match age > stale_after {
+                        true => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                        false => Style::default(),
+                    };
+                    Cell::from(Span::styled(right_align(format_age(age), 7), style))
+                }
+                None => Cell::from(""),
+            });
+        }
+        if columns.groups {
+            let group = stats.laggiest_group();
+            cells.push(Cell::from(right_align(
+                group
+                    .map(|g| format_number(g.lag as f64))
+                    .unwrap_or_default(),
+                7,
+            )));
+            cells.push(Cell::from(right_align(
+                group
+                    .and_then(|g| g.rate)
+                    .map(format_number)
+                    .unwrap_or_default(),
+                7,
+            )));
+        }
+        let row = Row::new(cells);
+        match scraper.is_alerting(topic) {
+            true => row.style(Style::default().add_modifier(Modifier::REVERSED)),
+            false => row,
+        }
+    }))
     .style(Style::default().fg(Color::White))
-    .header(Row::new(vec!["Topic", "Total", "Per Sec"]).style(Style::default()))
-    .widths(&[
-        Constraint::Length(30),
-        Constraint::Length(7),
-        Constraint::Length(7),
-    ])
+    .header(Row::new(header).style(Style::default()))
+    .widths(&TABLE_WIDTHS[..header_len])
     .column_spacing(1)
     .highlight_style(Style::default().add_modifier(Modifier::BOLD))
     .highlight_symbol(">")
 }
 
-fn format_time(time: DateTime<Local>, long: bool) -> String {
-    match long {
-        false => format!(
+#[derive(Debug, Clone, Copy)]
+enum TimeFormat {
+    Seconds,
+    Minutes,
+    Days,
+    Full,
+}
+
+impl TimeFormat {
+    fn for_interval(draw_interval: Duration) -> Self {
+        match draw_interval.as_secs() {
+            0..=3600 => TimeFormat::Seconds,
+            3601..=43200 => TimeFormat::Minutes,
+            43201..=604800 => TimeFormat::Days,
+            _ => TimeFormat::Full,
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            TimeFormat::Seconds => 8,
+            TimeFormat::Minutes => 5,
+            TimeFormat::Days => 11,
+            TimeFormat::Full => 19,
+        }
+    }
+}
+
+fn format_time(time: DateTime<Local>, format: TimeFormat) -> String {
+    match format {
+        TimeFormat::Seconds => format!(
             "{:02}:{:02}:{:02}",
             time.hour(),
             time.minute(),
             time.second()
         ),
-        true => format!(
+        TimeFormat::Minutes => format!("{:02}:{:02}", time.hour(), time.minute()),
+        TimeFormat::Days => format!(
+            "{:02}-{:02} {:02}:{:02}",
+            time.month(),
+            time.day(),
+            time.hour(),
+            time.minute()
+        ),
+        TimeFormat::Full => format!(
             "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
             time.year(),
             time.month(),
@@ -278,16 +905,283 @@ fn format_time(time: DateTime<Local>, long: bool) -> String {
     }
 }
 
-fn format_number(num: f64) -> String {
+const ZOOM_PRESETS: [Duration; 9] = [
+    Duration::from_secs(60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(15 * 60),
+    Duration::from_secs(30 * 60),
+    Duration::from_secs(3600),
+    Duration::from_secs(3 * 3600),
+    Duration::from_secs(6 * 3600),
+    Duration::from_secs(12 * 3600),
+    Duration::from_secs(24 * 3600),
+];
+
+fn zoom_in(draw_interval: Duration) -> Duration {
+    ZOOM_PRESETS
+        .iter()
+        .rev()
+        .find(|&&preset| preset < draw_interval)
+        .map_or(draw_interval, |&preset| preset)
+}
+
+fn zoom_out(draw_interval: Duration) -> Duration {
+    ZOOM_PRESETS
+        .iter()
+        .find(|&&preset| preset > draw_interval)
+        .map_or(draw_interval, |&preset| preset)
+}
+
+pub(crate) fn format_number(num: f64) -> String {
     match NumberPrefix::decimal(num) {
         NumberPrefix::Standalone(num) => format!("{:.2}", num),
         NumberPrefix::Prefixed(pfx, num) => format!("{:.2}{}", num, pfx),
     }
 }
 
-fn right_align(inp: String, len: usize) -> String {
+pub(crate) fn right_align(inp: String, len: usize) -> String {
     match len.checked_sub(inp.len()) {
         Some(0) | None => inp,
         Some(fill) => format!("{}{}", " ".repeat(fill), inp),
     }
 }
+
+fn mk_partition_table(partstats: &[stats::PartitionStats]) -> Table<'_> {
+    Table::new(partstats.iter().map(|p| {
+        let style = match p.seen {
+            // Suspicious: no traffic, at least not since we started looking
+            0 => Style::default().fg(Color::Red),
+            _ => Style::default().fg(partition_color(p.partition)),
+        };
+        Row::new(vec![
+            Cell::from(right_align(p.partition.to_string(), 4)),
+            Cell::from(right_align(
+                p.offset
+                    .map(|o| format_number(o as f64))
+                    .unwrap_or_default(),
+                7,
+            )),
+            Cell::from(right_align(
+                p.rate.map(format_number).unwrap_or_default(),
+                7,
+            )),
+            Cell::from(right_align(
+                p.leader.map(|l| l.to_string()).unwrap_or_default(),
+                6,
+            )),
+            Cell::from(p.isr.iter().join(",")),
+        ])
+        .style(style)
+    }))
+    .style(Style::default().fg(Color::White))
+    .header(Row::new(vec!["Part", "Offset", "Per Sec", "Leader", "ISR"]).style(Style::default()))
+    .widths(&[
+        Constraint::Length(4),
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Length(6),
+        Constraint::Length(18),
+    ])
+    .column_spacing(1)
+}
+
+/// One line about partitions that failed on their own, naming the first one
+fn partition_errors(errors: &HashMap<(TopicName, i32), String>) -> Option<String> {
+    let ((topic, partition), error) = errors
+        .iter()
+        .min_by_key(|((topic, partition), _)| (topic.as_str(), *partition))?;
+    Some(match errors.len() {
+        1 => format!("{}/{}: {}", topic, partition, error),
+        n => format!(
+            "{}/{}: {} (and {} more partitions)",
+            topic,
+            partition,
+            error,
+            n - 1
+        ),
+    })
+}
+
+fn mk_broker_table(brokers: &[stats::BrokerStats], now: Instant) -> Table<'_> {
+    Table::new(brokers.iter().map(|b| {
+        let (state, style) = match b.down {
+            Some(_) => ("DOWN", Style::default().fg(Color::Red)),
+            None => ("up", Style::default()),
+        };
+        Row::new(vec![
+            Cell::from(right_align(b.id.to_string(), 6)),
+            Cell::from(b.host.clone().unwrap_or_default()),
+            Cell::from(right_align(b.leaders.to_string(), 6)),
+            Cell::from(right_align(
+                b.last_ok
+                    .map(|t| format_ago(now.saturating_duration_since(t)))
+                    .unwrap_or_default(),
+                7,
+            )),
+            Cell::from(state),
+        ])
+        .style(style)
+    }))
+    .style(Style::default().fg(Color::White))
+    .header(Row::new(vec!["Broker", "Host", "Leads", "Last OK", "State"]).style(Style::default()))
+    .block(Block::default().borders(Borders::TOP))
+    .widths(&[
+        Constraint::Length(6),
+        Constraint::Length(21),
+        Constraint::Length(6),
+        Constraint::Length(7),
+        Constraint::Length(5),
+    ])
+    .column_spacing(1)
+}
+
+fn mk_alert_table<'a>(alerts: &[&'a Alert], now: Instant) -> Table<'a> {
+    Table::new(alerts.iter().map(|alert| {
+        Row::new(vec![
+            Cell::from(alert.topic.as_str()),
+            Cell::from(alert.rule.to_string()),
+            Cell::from(right_align(
+                alert.rate.map(format_number).unwrap_or_default(),
+                7,
+            )),
+            Cell::from(right_align(
+                format_age(now.saturating_duration_since(alert.since)),
+                5,
+            )),
+        ])
+    }))
+    .style(Style::default().fg(Color::Red))
+    .header(Row::new(vec!["Stalled", "Rule", "Per Sec", "Since"]).style(Style::default()))
+    .block(Block::default().borders(Borders::TOP))
+    .widths(&[
+        Constraint::Length(20),
+        Constraint::Length(20),
+        Constraint::Length(7),
+        Constraint::Length(5),
+    ])
+    .column_spacing(1)
+}
+
+fn format_ago(ago: Duration) -> String {
+    format!("{} ago", format_age(ago))
+}
+
+fn format_age(age: Duration) -> String {
+    match age.as_secs() {
+        s @ 0..=119 => format!("{}s", s),
+        s @ 120..=7199 => format!("{}m", s / 60),
+        s @ 7200..=172799 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tui::backend::TestBackend;
+
+    fn line(stacked: bool, data: Vec<(f64, f64)>) -> ChartLine {
+        ChartLine {
+            name: String::new(),
+            color: Color::White,
+            marker: symbols::Marker::Braille,
+            stacked,
+            data,
+        }
+    }
+
+    #[test]
+    fn stacks_by_bucket() {
+        let bucket = Duration::from_secs(10);
+        let mut data = vec![
+            line(true, vec![(-30., 1.), (-20., 2.), (-10., 3.)]),
+            // Started scraping a bit later
+            line(true, vec![(-19., 10.), (-9., 10.)]),
+            line(false, vec![(-20., 5.)]),
+        ];
+        stack(&mut data, bucket);
+        assert_eq!(data[0].data, [(-30., 1.), (-20., 2.), (-10., 3.)]);
+        assert_eq!(data[1].data, [(-19., 12.), (-9., 13.)]);
+        assert_eq!(data[2].data, [(-20., 5.)]);
+    }
+
+    #[test]
+    fn table_columns_fit() {
+        let (tx, rx) = mpsc::sync_channel(100);
+        let start = Instant::now();
+        for round in 0..2 {
+            let now = start + Duration::from_secs(10 * round);
+            tx.send(scrape::Message::PartitionOffsets {
+                now,
+                topic: "orders".into(),
+                partition: 0,
+                offset: 1_230_000 + 100 * round as i64,
+                low: 100 * round as i64,
+            })
+            .unwrap();
+            tx.send(scrape::Message::RoundFinished {
+                now,
+                topic: "orders".into(),
+                skipped: 0,
+            })
+            .unwrap();
+        }
+        let mut scraper = Stats::ingesting(
+            rx,
+            Duration::from_secs(10),
+            Duration::from_secs(3600),
+            Clock::Real,
+        )
+        .unwrap();
+        scraper.ingest().unwrap();
+        let basestats = scraper.sorted_basestats();
+        let columns = Columns::for_stats(&basestats);
+        let table = mk_table(
+            &basestats,
+            &scraper,
+            &ColorAssignment::new(),
+            columns,
+            &HashSet::new(),
+            Duration::from_secs(60),
+        );
+        let mut terminal = Terminal::new(TestBackend::new(columns.width(), 2)).unwrap();
+        terminal.draw(|f| f.render_widget(table, f.size())).unwrap();
+        let buffer = terminal.backend().buffer();
+        let lines = (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer.get(x, y).symbol.as_str())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            lines[0].contains("Total   Retained Per Sec Del/s"),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[1].contains("  1.23M   10.00   10.00"), "{}", lines[1]);
+    }
+
+    #[test]
+    fn log_labels_match_scale() {
+        let mode = ChartMode {
+            log: true,
+            stacked: false,
+            unit: stats::Unit::Messages,
+        };
+        // Halfway up a log chart to 99 is where a rate of 9 is drawn
+        assert_eq!(y_labels(mode, 99., 2), ["0.00", "9.00", "99.00"]);
+        assert!((mode.scale(9.) - mode.scale(99.) / 2.).abs() < 1e-12);
+        // Below 1 the scale is still log(1 + v), not clamped to 1
+        assert_eq!(y_labels(mode, 0.5, 2), ["0.00", "0.22", "0.50"]);
+        assert!((mode.scale(0.2247) - mode.scale(0.5) / 2.).abs() < 1e-4);
+        // Nothing plotted yet, and anything below zero sits on the floor
+        assert_eq!(y_labels(mode, 0., 2), ["0.00", "0.00", "0.00"]);
+        assert_eq!(mode.scale(-5.), 0.);
+        let linear = ChartMode { log: false, ..mode };
+        assert_eq!(
+            y_labels(linear, 100., 4),
+            ["0.00", "25.00", "50.00", "75.00", "100.00"]
+        );
+    }
+}
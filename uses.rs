@@ -2,13 +2,22 @@ pub use anyhow::{Context, Result};
 pub use chrono::{DateTime, Datelike, Local, Timelike};
 pub use itertools::Itertools;
 pub use number_prefix::NumberPrefix;
-pub use rand::Rng;
-pub use rdkafka::{error::KafkaError, metadata::MetadataPartition, ClientConfig as KafkaConfig};
+pub use rdkafka::{
+    consumer::Consumer as _,
+    error::{KafkaError, KafkaResult},
+    ClientConfig as KafkaConfig, Offset, TopicPartitionList,
+};
+pub use regex::Regex;
+pub use serde::{Deserialize, Serialize};
 pub use std::{
+    borrow::Cow,
     cmp,
-    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    collections::{btree_map, hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
     io,
+    io::Write,
     iter::once,
+    mem,
+    path::PathBuf,
     sync::{
         mpsc::{self, Receiver},
         Arc, Mutex,
@@ -19,8 +28,13 @@ pub use std::{
 pub use structopt::StructOpt;
 
 pub use crate::{
+    clock::Clock,
     colors::ColorAssignment,
-    scrape, stats,
+    grouping::Grouping,
+    intern::TopicName,
+    scrape,
+    series::Series,
+    stats,
     stats::{Stats, Topic},
     ui, Opts,
 };